@@ -0,0 +1,180 @@
+//! Selection of alternates, package entries that are only installed on some
+//! machines.
+//!
+//! An alternate is named `<name>##<conditions>`, where the conditions are
+//! separated by commas and all of them have to hold:
+//! - `host.<hostname>`
+//! - `user.<user>`
+//! - `class.<class>`, the class given with `--class` or in the configuration
+//! - `os.<os>`, as in `std::env::consts::OS`, e.g. `linux` or `macos`
+//! - `default`, which always holds
+//!
+//! Of the entries of a directory that share a name, the one with the highest
+//! score is installed as `<name>`, and the others are left out of the package.
+//! Each condition adds to the score, `host` 8, `user` 4, `class` 2, `os` 1
+//! and `default` 0, so a more specific alternate wins. An entry named just
+//! `<name>` scores 0 as well. Ties are broken by taking the entry whose name
+//! sorts first, which makes `<name>` win over `<name>##default`. If no entry
+//! matches, nothing is installed.
+//!
+//! Directories can be alternates too, in which case their contents are
+//! installed below `<name>`.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+use crate::template::Variables;
+
+pub const SEPARATOR: &str = "##";
+
+/// What alternates are selected for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Machine {
+    pub hostname: String,
+    pub os: String,
+    pub user: String,
+    pub class: String,
+}
+
+impl Machine {
+    pub fn of(variables: &Variables) -> Self {
+        Self {
+            hostname: variables.hostname.clone(),
+            os: variables.os.clone(),
+            user: variables.user.clone(),
+            class: variables.class.clone(),
+        }
+    }
+
+    /// Returns the score of an entry with `conditions`, or `None` if one of
+    /// them does not hold.
+    fn score(&self, conditions: &str) -> Option<u32> {
+        let mut score = 0;
+        for condition in conditions.split(',') {
+            score += match condition.split_once('.') {
+                // What the machine does not know is empty, and must not match.
+                Some((_, "")) => return None,
+                Some(("host", host)) if host == self.hostname => 8,
+                Some(("user", user)) if user == self.user => 4,
+                Some(("class", class)) if class == self.class => 2,
+                Some(("os", os)) if os == self.os => 1,
+                None if condition == "default" => 0,
+                _ => return None,
+            };
+        }
+        Some(score)
+    }
+}
+
+/// Splits `name` into the name it is installed as and its conditions, if it
+/// is an alternate.
+fn split(name: &OsStr) -> Option<(&str, &str)> {
+    name.to_str()?.split_once(SEPARATOR).filter(|(base, _)| !base.is_empty())
+}
+
+/// Returns true if the last component of `item` is an alternate.
+pub fn is_alternate(item: &Path) -> bool {
+    item.file_name().and_then(split).is_some()
+}
+
+/// Returns `item`, a path relative to the package root, with the conditions
+/// removed from every component that is an alternate.
+pub fn base_path(item: &Path) -> PathBuf {
+    item.components()
+        .map(|component| match component {
+            Component::Normal(name) => match split(name) {
+                Some((base, _)) => PathBuf::from(base),
+                None => PathBuf::from(name),
+            },
+            _ => PathBuf::from(component.as_os_str()),
+        })
+        .collect()
+}
+
+/// Returns, for each of `items`, which are the entries of one directory,
+/// whether it is selected for `machine`.
+pub fn selected<'a, I>(items: I, machine: &Machine) -> Vec<bool>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    let items: Vec<&Path> = items.into_iter().collect();
+    let names: Vec<&OsStr> = items.iter().map(|item| item.file_name().unwrap_or_default()).collect();
+
+    // The best entry for each name, by index into `items`.
+    let mut best: HashMap<&OsStr, (u32, usize)> = HashMap::new();
+    for (index, name) in names.iter().enumerate() {
+        let (base, score) = match split(name) {
+            Some((base, conditions)) => match machine.score(conditions) {
+                Some(score) => (OsStr::new(base), score),
+                None => continue,
+            },
+            None => (*name, 0),
+        };
+        best.entry(base)
+            .and_modify(|(best_score, best_index)| {
+                if score > *best_score || (score == *best_score && names[index] < names[*best_index]) {
+                    *best_score = score;
+                    *best_index = index;
+                }
+            })
+            .or_insert((score, index));
+    }
+
+    let mut selected = vec![false; items.len()];
+    for (_, index) in best.into_values() {
+        selected[index] = true;
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base_path() {
+        assert_eq!(base_path(Path::new(".bashrc##host.laptop")), PathBuf::from(".bashrc"));
+        assert_eq!(
+            base_path(Path::new(".config##os.linux/app##default/rc")),
+            PathBuf::from(".config/app/rc")
+        );
+        assert_eq!(base_path(Path::new("##default")), PathBuf::from("##default"));
+    }
+
+    #[test]
+    fn test_selected() {
+        let machine = Machine {
+            hostname: "laptop".to_string(),
+            os: "linux".to_string(),
+            user: "me".to_string(),
+            class: "work".to_string(),
+        };
+        let select = |names: &[&'static str]| -> Vec<&'static str> {
+            let items: Vec<PathBuf> = names.iter().map(PathBuf::from).collect();
+            let selected = selected(items.iter().map(PathBuf::as_path), &machine);
+            names.iter().zip(selected).filter(|(_, selected)| *selected).map(|(name, _)| *name).collect()
+        };
+
+        assert_eq!(
+            select(&[".bashrc##default", ".bashrc##host.laptop", ".bashrc##os.linux", ".profile"]),
+            vec![".bashrc##host.laptop", ".profile"]
+        );
+        assert_eq!(
+            select(&[".bashrc##class.work,os.linux", ".bashrc##user.me"]),
+            vec![".bashrc##user.me"]
+        );
+        assert_eq!(select(&[".bashrc", ".bashrc##default"]), vec![".bashrc"]);
+        assert_eq!(select(&[".bashrc##host.desktop", ".bashrc##os.macos,default"]), Vec::<&str>::new());
+        assert_eq!(select(&[".bashrc##host.laptop", ".bashrc##os.linux,class.work,user.me"]), vec![".bashrc##host.laptop"]);
+    }
+
+    #[test]
+    fn test_empty_values_never_match() {
+        let machine = Machine::default();
+        for conditions in ["host.", "user.", "class.", "os.", "os.,default"] {
+            assert_eq!(machine.score(conditions), None, "{}", conditions);
+        }
+        assert_eq!(machine.score("default"), Some(0));
+    }
+}
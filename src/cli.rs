@@ -1,17 +1,35 @@
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::fs::{InstallMode, LinkMode};
 
 #[derive(Parser)]
-#[command(trailing_var_arg = true)]
+#[command(trailing_var_arg = true, after_help = crate::exit::HELP)]
 pub struct Cli {
     // #[arg(short = 'S', long = "stow", help("Stow"), default_value_t=true)]
     // pub stow: bool,
+    #[arg(
+        long = "output",
+        value_enum,
+        global = true,
+        help("Format of the output. 'json' prints a single versioned JSON document"),
+        default_value_t = OutputFormat::Text
+    )]
+    pub output: OutputFormat,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Parser, Debug, Default)]
 pub struct StowArgs {
     #[arg(
         short = 'd',
@@ -34,10 +52,19 @@ pub struct StowArgs {
         short = 'v',
         long = "verbose",
         help("Print some extra info during run"),
-        default_value_t = false
+        default_value_t = false,
+        overrides_with = "no_verbose"
     )]
     pub verbose: bool,
 
+    #[arg(
+        long = "no-verbose",
+        help("Do not print extra info, even if the configuration asks for it"),
+        default_value_t = false,
+        overrides_with = "verbose"
+    )]
+    pub no_verbose: bool,
+
     #[arg(
         short = 'n',
         long = "no",
@@ -47,9 +74,238 @@ pub struct StowArgs {
         default_value_t = false
     )]
     pub simulate: bool,
+
+    #[arg(
+        long = "adopt",
+        help(
+            "Move files that are in the way of a link into the package, replacing the package's copy, and link them"
+        ),
+        default_value_t = false,
+        overrides_with = "no_adopt"
+    )]
+    pub adopt: bool,
+
+    #[arg(
+        long = "no-adopt",
+        help("Do not adopt files, even if the configuration asks for it"),
+        default_value_t = false,
+        overrides_with = "adopt"
+    )]
+    pub no_adopt: bool,
+
+    #[arg(
+        long = "dotfiles",
+        help("Link package entries named 'dot-*' as '.*' in the target, e.g. 'dot-bashrc' as '.bashrc'"),
+        default_value_t = false,
+        overrides_with = "no_dotfiles"
+    )]
+    pub dotfiles: bool,
+
+    #[arg(
+        long = "no-dotfiles",
+        help("Do not translate 'dot-*' entries, even if the configuration asks for it"),
+        default_value_t = false,
+        overrides_with = "dotfiles"
+    )]
+    pub no_dotfiles: bool,
+
+    #[arg(
+        long = "ignore",
+        value_name = "REGEX",
+        help(
+            "Ignore package entries whose name or package-relative path matches REGEX (or a glob prefixed with 'glob:'). Can be given multiple times"
+        )
+    )]
+    pub ignore: Vec<String>,
+
+    #[arg(
+        long = "link-mode",
+        value_enum,
+        help("Write link targets relative to the link (the default) or as absolute paths")
+    )]
+    pub link_mode: Option<LinkMode>,
+
+    #[arg(
+        long = "install-mode",
+        value_enum,
+        help("Link package entries (the default), or copy or hard link package files into the target")
+    )]
+    pub install_mode: Option<InstallMode>,
+
+    #[arg(
+        long = "var",
+        value_name = "NAME=VALUE",
+        help("Set a variable for templates, available as vars.NAME. Can be given multiple times")
+    )]
+    pub vars: Vec<String>,
+
+    #[arg(
+        long = "class",
+        help("Select alternates named with class.CLASS, e.g. work or personal")
+    )]
+    pub class: Option<String>,
+
+    #[arg(
+        long = "force",
+        help("Unstow packages even if other stowed packages depend on them"),
+        default_value_t = false
+    )]
+    pub force: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ListArgs {
+    #[arg(
+        short = 'd',
+        long = "dir",
+        help("Directory containing the packages")
+    )]
+    pub package_dir: Option<PathBuf>,
+
+    #[arg(
+        short = 't',
+        long = "target",
+        help("Directory to check for links to the packages")
+    )]
+    pub target_dir: Option<PathBuf>,
+
+    #[arg(
+        long = "stowed",
+        help("Only list packages that have links in the target"),
+        default_value_t = false,
+        conflicts_with = "unstowed"
+    )]
+    pub stowed: bool,
+
+    #[arg(
+        long = "unstowed",
+        help("Only list packages that have no links in the target"),
+        default_value_t = false
+    )]
+    pub unstowed: bool,
+
+    #[arg(
+        short = 'v',
+        long = "verbose",
+        help("Print some extra info during run"),
+        default_value_t = false,
+        overrides_with = "no_verbose"
+    )]
+    pub verbose: bool,
+
+    #[arg(
+        long = "no-verbose",
+        help("Do not print extra info, even if the configuration asks for it"),
+        default_value_t = false,
+        overrides_with = "verbose"
+    )]
+    pub no_verbose: bool,
+
+    #[arg(
+        long = "dotfiles",
+        help("Expect package entries named 'dot-*' to be linked as '.*'"),
+        default_value_t = false,
+        overrides_with = "no_dotfiles"
+    )]
+    pub dotfiles: bool,
+
+    #[arg(
+        long = "no-dotfiles",
+        help("Do not translate 'dot-*' entries, even if the configuration asks for it"),
+        default_value_t = false,
+        overrides_with = "dotfiles"
+    )]
+    pub no_dotfiles: bool,
+
+    #[arg(
+        long = "ignore",
+        value_name = "REGEX",
+        help("Ignore package entries whose name or package-relative path matches REGEX")
+    )]
+    pub ignore: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct DoctorArgs {
+    #[arg(
+        short = 'd',
+        long = "dir",
+        help("Directory containing the packages")
+    )]
+    pub package_dir: Option<PathBuf>,
+
+    #[arg(
+        short = 't',
+        long = "target",
+        help("Directory to check for broken links")
+    )]
+    pub target_dir: Option<PathBuf>,
+
+    #[arg(
+        long = "fix",
+        help("Remove dangling links and links to ignored files"),
+        default_value_t = false
+    )]
+    pub fix: bool,
+
+    #[arg(
+        short = 'v',
+        long = "verbose",
+        help("Print some extra info during run"),
+        default_value_t = false,
+        overrides_with = "no_verbose"
+    )]
+    pub verbose: bool,
+
+    #[arg(
+        long = "no-verbose",
+        help("Do not print extra info, even if the configuration asks for it"),
+        default_value_t = false,
+        overrides_with = "verbose"
+    )]
+    pub no_verbose: bool,
+
+    #[arg(
+        short = 'n',
+        long = "no",
+        help("With --fix, do not remove anything. Just show what would be removed"),
+        default_value_t = false
+    )]
+    pub simulate: bool,
+
+    #[arg(
+        long = "dotfiles",
+        help("Expect package entries named 'dot-*' to be linked as '.*'"),
+        default_value_t = false,
+        overrides_with = "no_dotfiles"
+    )]
+    pub dotfiles: bool,
+
+    #[arg(
+        long = "no-dotfiles",
+        help("Do not translate 'dot-*' entries, even if the configuration asks for it"),
+        default_value_t = false,
+        overrides_with = "dotfiles"
+    )]
+    pub no_dotfiles: bool,
+
+    #[arg(
+        long = "ignore",
+        value_name = "REGEX",
+        help("Ignore package entries whose name or package-relative path matches REGEX")
+    )]
+    pub ignore: Vec<String>,
+
+    #[arg(
+        long = "link-mode",
+        value_enum,
+        help("Report links that are not written the way this mode writes them, relative (the default) or absolute")
+    )]
+    pub link_mode: Option<LinkMode>,
 }
 
 pub type UnstowArgs = StowArgs;
+pub type StatusArgs = StowArgs;
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
@@ -61,4 +317,13 @@ pub enum Commands {
 
     #[command(about = "Unstow and restow packages")]
     Restow(StowArgs),
+
+    #[command(about = "Show whether packages are stowed into a target directory")]
+    Status(StatusArgs),
+
+    #[command(about = "List the packages in the stow directory")]
+    List(ListArgs),
+
+    #[command(about = "Find broken links into the stow directory")]
+    Doctor(DoctorArgs),
 }
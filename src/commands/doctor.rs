@@ -0,0 +1,96 @@
+use crate::cli::{DoctorArgs, OutputFormat, StowArgs};
+use crate::doctor::{find_problems, Finding};
+use crate::error::Error;
+use crate::exec::Executor;
+use crate::fs::{Action, Target};
+use crate::report::{self, ActionReport, DoctorReport, ProblemReport};
+use crate::state::State;
+
+use super::{Completion, Context};
+
+/// Reports the links in the target that point into the stow directory but
+/// are not links syra would create, and removes the broken ones with `--fix`.
+pub fn run(args: DoctorArgs, output: OutputFormat) -> Result<Completion, Error> {
+    let mut report = DoctorReport::new(args.simulate);
+    let result = execute(args, output, &mut report);
+    if output == OutputFormat::Json {
+        report.error = result.as_ref().err().map(Error::to_string);
+        report::print(&report)?;
+    }
+    result
+}
+
+fn execute(args: DoctorArgs, output: OutputFormat, report: &mut DoctorReport) -> Result<Completion, Error> {
+    let DoctorArgs {
+        package_dir,
+        target_dir,
+        fix,
+        verbose,
+        no_verbose,
+        simulate,
+        dotfiles,
+        no_dotfiles,
+        ignore,
+        link_mode,
+    } = args;
+    let args = StowArgs {
+        package_dir,
+        target_dir,
+        verbose,
+        no_verbose,
+        simulate,
+        dotfiles,
+        no_dotfiles,
+        ignore,
+        link_mode,
+        ..Default::default()
+    };
+    let context = Context::new(args, output)?;
+    let verbose = context.args.verbose;
+    let text = output == OutputFormat::Text;
+
+    let findings = find_problems(context.target.path(), &context.package_dir, &context.options)?;
+    if text {
+        for Finding { link, problem } in &findings {
+            println!("{:9}  {} -> {}", problem, link.path.display(), link.target.display());
+        }
+    }
+    if verbose {
+        println!("{} problem(s) found", findings.len());
+    }
+    report.problems = findings
+        .iter()
+        .map(|Finding { link, problem }| ProblemReport {
+            problem: *problem,
+            path: link.path.to_string_lossy().into_owned(),
+            target: link.target.to_string_lossy().into_owned(),
+            fixable: problem.is_fixable(),
+        })
+        .collect();
+
+    let actions: Vec<Action> = findings
+        .into_iter()
+        .filter(|finding| fix && finding.problem.is_fixable())
+        .map(|finding| Action::Unlink(finding.link.path))
+        .collect();
+    let result = if actions.is_empty() {
+        Ok(Completion::Done)
+    } else if simulate {
+        if text {
+            for action in &actions {
+                println!("{}", action);
+            }
+        }
+        Ok(Completion::ChangesPending)
+    } else {
+        let mut state = State::load(context.target.path())?;
+        let result = Executor::new(&mut state, context.options.log.clone()).execute(&actions);
+        state.save()?;
+        result.map(|()| Completion::Done)
+    };
+
+    if result.is_ok() {
+        report.fixes = actions.iter().map(ActionReport::from).collect();
+    }
+    result
+}
@@ -0,0 +1,132 @@
+use crate::cli::{ListArgs, OutputFormat, StowArgs};
+use crate::error::Error;
+use crate::fs::{InstalledItem, Target};
+use crate::report::{self, PackageReport, PackagesReport};
+use crate::run::package_names;
+use crate::state::State;
+use crate::status::{package_status, PackageStatus};
+
+use super::Context;
+
+/// Lists the packages in the stow directory along with their status in the target.
+pub fn run(args: ListArgs, output: OutputFormat) -> Result<(), Error> {
+    let mut report = PackagesReport::new("list");
+    let result = execute(args, output, &mut report);
+    if output == OutputFormat::Json {
+        report.error = result.as_ref().err().map(Error::to_string);
+        report::print(&report)?;
+    }
+    result
+}
+
+fn execute(args: ListArgs, output: OutputFormat, report: &mut PackagesReport) -> Result<(), Error> {
+    let ListArgs {
+        package_dir,
+        target_dir,
+        stowed,
+        unstowed,
+        verbose,
+        no_verbose,
+        dotfiles,
+        no_dotfiles,
+        ignore,
+    } = args;
+    let args = StowArgs {
+        package_dir,
+        target_dir,
+        verbose,
+        no_verbose,
+        dotfiles,
+        no_dotfiles,
+        ignore,
+        ..Default::default()
+    };
+    let context = Context::new(args, output)?;
+    let state = State::load(context.target.path())?;
+
+    let names = package_names(&context.package_dir)?;
+    let width = names.iter().map(String::len).max().unwrap_or(0);
+    for name in names {
+        // A package that cannot be read is listed with its error, so that
+        // it does not hide the others.
+        let (status, error) = match listed_status(&context, &state, &name, stowed, unstowed) {
+            Ok(Some(status)) => (status, None),
+            Ok(None) => continue,
+            Err(e) => (PackageStatus::Unreadable, Some(e.to_string())),
+        };
+        if output == OutputFormat::Json {
+            report.packages.push(PackageReport {
+                name,
+                status,
+                items: None,
+                conflicts: None,
+                alternates: None,
+                error,
+            });
+        } else {
+            match error {
+                Some(error) => println!("{:width$}  {}: {}", name, status, error, width = width),
+                None => println!("{:width$}  {}", name, status, width = width),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the status of the package `name`, or `None` if `--stowed` or
+/// `--unstowed` leave it out.
+fn listed_status(
+    context: &Context,
+    state: &State,
+    name: &str,
+    stowed: bool,
+    unstowed: bool,
+) -> Result<Option<PackageStatus>, Error> {
+    let package = context.package(name)?;
+    let items = context.target.get_installed_package_contents(&package, state)?;
+    let any_owned = items.iter().any(InstalledItem::is_owned);
+    if (stowed && !any_owned) || (unstowed && any_owned) {
+        return Ok(None);
+    }
+    let (status, _) = package_status(&context.target, state, &context.options, &package, &items)?;
+    Ok(Some(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::tests::{create_files, setup};
+
+    #[test]
+    fn test_list_reports_unreadable_packages() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "b/.vimrc", "c/.profile"]);
+        std::fs::write(stow_dir.join("b/syra.toml"), "requires = ['x']\n").unwrap();
+        let args = ListArgs {
+            package_dir: Some(stow_dir),
+            target_dir: Some(target_dir),
+            stowed: false,
+            unstowed: false,
+            verbose: false,
+            no_verbose: false,
+            dotfiles: false,
+            no_dotfiles: false,
+            ignore: Vec::new(),
+        };
+
+        let mut report = PackagesReport::new("list");
+        execute(args, OutputFormat::Json, &mut report).unwrap();
+
+        let statuses: Vec<(&str, PackageStatus)> =
+            report.packages.iter().map(|package| (package.name.as_str(), package.status)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("a", PackageStatus::NotStowed),
+                ("b", PackageStatus::Unreadable),
+                ("c", PackageStatus::NotStowed),
+            ]
+        );
+        assert!(report.packages[1].error.as_deref().unwrap().contains("syra.toml"));
+    }
+}
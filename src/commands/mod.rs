@@ -1,3 +1,299 @@
+pub mod doctor;
+pub mod list;
+pub mod restow;
+pub mod status;
 pub mod stow;
 pub mod unstow;
 
+use std::env::current_dir;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{OutputFormat, StowArgs};
+use crate::config;
+use crate::error::{Error, IoAction, IoContext};
+use crate::fs::{PackageImpl, Target, TargetImpl};
+use crate::ignore::IgnoreList;
+use crate::log::Log;
+use crate::plan::{Operation, Options};
+use crate::report::{self, OperationReport};
+use crate::run::{load_package, resolve_dirs, Syra};
+use crate::template::Variables;
+
+pub use crate::run::Completion;
+
+/// The directories and options of a run, with the defaults from the
+/// configuration files applied.
+struct Context {
+    args: StowArgs,
+    package_dir: PathBuf,
+    target: TargetImpl,
+    options: Options,
+}
+
+impl Context {
+    fn new(mut args: StowArgs, output: OutputFormat) -> Result<Self, Error> {
+        // Verbose messages would be mixed into the JSON document on stdout.
+        args.no_verbose |= output == OutputFormat::Json;
+        let args = config::with_defaults(args)?;
+        let cwd = current_dir().at(IoAction::CurrentDir, Path::new("."))?;
+        let (package_dir, target_dir) =
+            resolve_dirs(args.package_dir.as_deref(), args.target_dir.as_deref(), &cwd)?;
+
+        let target = TargetImpl::new(&target_dir)?.with_dotfiles(args.dotfiles);
+        let options = Options {
+            log: if args.verbose {
+                Log::new(|message| println!("{}", message))
+            } else {
+                Log::default()
+            },
+            adopt: args.adopt,
+            dotfiles: args.dotfiles,
+            link_mode: args.link_mode.unwrap_or_default(),
+            install_mode: args.install_mode.unwrap_or_default(),
+            variables: Variables::new(&args.vars)?.with_class(args.class.as_deref()),
+            ignore: IgnoreList::new(&args.ignore)?,
+        };
+
+        Ok(Self {
+            args,
+            package_dir,
+            target,
+            options,
+        })
+    }
+
+    /// Returns the packages named on the command line.
+    fn packages(&self) -> Result<Vec<PackageImpl>, Error> {
+        self.args.packages.iter().map(|name| self.package(name)).collect()
+    }
+
+    fn package(&self, name: &str) -> Result<PackageImpl, Error> {
+        load_package(&self.package_dir, name, &self.options)
+    }
+
+    /// Returns the runner for stow, unstow and restow, printing as the
+    /// command line does when the output is `text`.
+    fn syra(&self, text: bool) -> Syra {
+        Syra {
+            stow_dir: Some(self.package_dir.clone()),
+            target_dir: Some(self.target.path().to_path_buf()),
+            options: self.options.clone(),
+            simulate: self.args.simulate,
+            force: self.args.force,
+            print: text,
+        }
+    }
+}
+
+/// Plans `operation` for every package in `args` and, unless the plan has
+/// conflicts, executes it.
+fn run_operation(output: OutputFormat, operation: Operation, args: StowArgs) -> Result<Completion, Error> {
+    let mut report = OperationReport::new(operation, &args.packages, args.simulate);
+    let result = execute_operation(output, operation, args, &mut report);
+    if output == OutputFormat::Json {
+        report.finish(&result);
+        report::print(&report)?;
+    }
+    result
+}
+
+fn execute_operation(
+    output: OutputFormat,
+    operation: Operation,
+    args: StowArgs,
+    report: &mut OperationReport,
+) -> Result<Completion, Error> {
+    let context = Context::new(args, output)?;
+    let text = output == OutputFormat::Text;
+    report.simulate = context.args.simulate;
+    context.syra(text).execute(operation, &context.args.packages, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::tests::{create_files, setup};
+    use crate::report::ActionReport;
+    use std::fs::write;
+
+    fn run_packages(
+        stow_dir: &Path,
+        target_dir: &Path,
+        operation: Operation,
+        packages: &[&str],
+        force: bool,
+    ) -> (Result<Completion, Error>, OperationReport) {
+        let packages: Vec<String> = packages.iter().map(|name| name.to_string()).collect();
+        let mut report = OperationReport::new(operation, &packages, false);
+        let args = StowArgs {
+            package_dir: Some(stow_dir.to_path_buf()),
+            target_dir: Some(target_dir.to_path_buf()),
+            packages,
+            force,
+            ..Default::default()
+        };
+        let result = execute_operation(OutputFormat::Json, operation, args, &mut report);
+        (result, report)
+    }
+
+    #[test]
+    fn test_stow_resolves_dependencies() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["nvim/.config/nvim/init.lua", "fonts/.fonts/mono.ttf", "shell/.profile"]);
+        write(stow_dir.join("nvim/syra.toml"), "depends = ['shell', 'fonts']\n").unwrap();
+        write(stow_dir.join("shell/syra.toml"), "depends = ['fonts']\n").unwrap();
+
+        let (result, report) = run_packages(&stow_dir, &target_dir, Operation::Stow, &["nvim"], false);
+        result.unwrap();
+        assert_eq!(report.packages, vec!["fonts", "shell", "nvim"]);
+        assert!(target_dir.join(".fonts").is_symlink());
+        assert!(target_dir.join(".profile").is_symlink());
+        assert!(target_dir.join(".config").is_symlink());
+        assert!(!target_dir.join("syra.toml").exists());
+
+        write(stow_dir.join("fonts/syra.toml"), "depends = ['nvim']\n").unwrap();
+        let (result, _) = run_packages(&stow_dir, &target_dir, Operation::Restow, &["nvim"], false);
+        assert!(matches!(result, Err(Error::DependencyCycle(cycle)) if cycle == ["nvim", "shell", "fonts", "nvim"]));
+    }
+
+    #[test]
+    fn test_stow_refuses_conflicting_packages() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["vim/.vimrc", "nvim/.config/nvim/init.lua", "emacs/.emacs"]);
+        write(stow_dir.join("nvim/syra.toml"), "conflicts = ['vim']\n").unwrap();
+        write(stow_dir.join("emacs/syra.toml"), "conflicts = ['nvim']\n").unwrap();
+
+        let (result, _) = run_packages(&stow_dir, &target_dir, Operation::Stow, &["vim", "nvim"], false);
+        assert!(matches!(result, Err(Error::Conflicts(conflicts)) if matches!(&conflicts[..], [Error::PackageConflict { .. }])));
+        assert!(!target_dir.join(".vimrc").exists());
+
+        run_packages(&stow_dir, &target_dir, Operation::Stow, &["vim"], false).0.unwrap();
+        let (result, report) = run_packages(&stow_dir, &target_dir, Operation::Stow, &["nvim"], false);
+        assert!(matches!(result, Err(Error::Conflicts(_))));
+        assert_eq!(report.conflicts[0].kind, "package_conflict");
+
+        // A stowed package can declare the conflict as well.
+        run_packages(&stow_dir, &target_dir, Operation::Stow, &["emacs"], false).0.unwrap();
+        run_packages(&stow_dir, &target_dir, Operation::Unstow, &["vim"], false).0.unwrap();
+        let (result, _) = run_packages(&stow_dir, &target_dir, Operation::Stow, &["nvim"], false);
+        assert!(matches!(
+            result,
+            Err(Error::Conflicts(conflicts))
+                if matches!(&conflicts[..], [Error::PackageConflict { package, .. }] if package == "emacs")
+        ));
+    }
+
+    #[test]
+    fn test_unstow_refuses_needed_packages() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["nvim/.config/nvim/init.lua", "fonts/.fonts/mono.ttf"]);
+        write(stow_dir.join("nvim/syra.toml"), "depends = ['fonts']\n").unwrap();
+        run_packages(&stow_dir, &target_dir, Operation::Stow, &["nvim"], false).0.unwrap();
+
+        let (result, report) = run_packages(&stow_dir, &target_dir, Operation::Unstow, &["fonts"], false);
+        assert!(matches!(result, Err(Error::Conflicts(_))));
+        assert_eq!(report.conflicts[0].kind, "package_needed");
+        assert!(target_dir.join(".fonts").is_symlink());
+
+        // Unstowing the dependent package along with it is fine.
+        let (result, _) = run_packages(&stow_dir, &target_dir, Operation::Unstow, &["fonts", "nvim"], false);
+        result.unwrap();
+        assert!(!target_dir.join(".config").exists());
+
+        run_packages(&stow_dir, &target_dir, Operation::Stow, &["nvim"], false).0.unwrap();
+        run_packages(&stow_dir, &target_dir, Operation::Unstow, &["fonts"], true).0.unwrap();
+        assert!(!target_dir.join(".fonts").exists());
+        assert!(target_dir.join(".config").is_symlink());
+    }
+
+    #[test]
+    fn test_malformed_manifest_only_fails_its_own_package() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["vim/.vimrc", "broken/.broken", "nvim/.config/nvim/init.lua"]);
+        write(stow_dir.join("broken/syra.toml"), "requires = ['vim']\n").unwrap();
+        write(stow_dir.join("nvim/syra.toml"), "conflicts = ['broken']\n").unwrap();
+
+        run_packages(&stow_dir, &target_dir, Operation::Stow, &["vim", "nvim"], false).0.unwrap();
+        run_packages(&stow_dir, &target_dir, Operation::Unstow, &["vim"], false).0.unwrap();
+        assert!(!target_dir.join(".vimrc").exists());
+
+        let (result, _) = run_packages(&stow_dir, &target_dir, Operation::Stow, &["broken"], false);
+        assert!(matches!(result, Err(Error::InvalidConfig(..))));
+        write(stow_dir.join("vim/syra.toml"), "depends = ['broken']\n").unwrap();
+        let (result, _) = run_packages(&stow_dir, &target_dir, Operation::Stow, &["vim"], false);
+        assert!(matches!(result, Err(Error::InvalidConfig(..))));
+    }
+
+    #[test]
+    fn test_stow_runs_hooks() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "b/.vimrc"]);
+        write(
+            stow_dir.join("a/syra.toml"),
+            "[hooks]\npost-stow = 'echo \"$SYRA_HOOK $SYRA_PACKAGE $SYRA_CHANGED_PATHS\" > ../hook.log'\npre-unstow = 'false'\n",
+        )
+        .unwrap();
+        write(stow_dir.join("b/syra.toml"), "[hooks]\npost-stow = 'exit 3'\n").unwrap();
+
+        // A failed post hook only rolls back its own package.
+        let (result, report) = run_packages(&stow_dir, &target_dir, Operation::Stow, &["a", "b"], false);
+        assert!(matches!(
+            result,
+            Err(Error::RolledBack(rollback)) if matches!(&rollback.error, Error::Hook { package, .. } if package == "b")
+        ));
+        assert_eq!(report.executed.len(), 1);
+        assert!(target_dir.join(".bashrc").is_symlink());
+        assert!(!target_dir.join(".vimrc").exists());
+        assert_eq!(
+            std::fs::read_to_string(stow_dir.join("hook.log")).unwrap(),
+            format!("post-stow a {}\n", target_dir.join(".bashrc").display())
+        );
+
+        // A failed pre hook leaves its package alone.
+        let (result, _) = run_packages(&stow_dir, &target_dir, Operation::Unstow, &["a"], false);
+        assert!(matches!(result, Err(Error::Hook { hook: "pre-unstow", .. })));
+        assert!(target_dir.join(".bashrc").is_symlink());
+    }
+
+    #[test]
+    fn test_failed_hook_keeps_links_unfolded_for_earlier_packages() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/nvim/init.lua", "b/.config/git/config"]);
+        write(stow_dir.join("b/syra.toml"), "[hooks]\npost-stow = 'false'\n").unwrap();
+
+        // b unfolds the .config link that a planned, and then fails.
+        let (result, report) = run_packages(&stow_dir, &target_dir, Operation::Stow, &["a", "b"], false);
+        assert!(matches!(result, Err(Error::RolledBack(_))));
+        assert!(target_dir.join(".config").is_dir() && !target_dir.join(".config").is_symlink());
+        assert!(target_dir.join(".config/nvim").is_symlink());
+        assert!(!target_dir.join(".config/git").exists());
+        assert_eq!(
+            report.executed,
+            vec![
+                ActionReport::CreateDir {
+                    path: target_dir.join(".config").display().to_string()
+                },
+                ActionReport::Link {
+                    path: target_dir.join(".config/nvim").display().to_string(),
+                    target: "../../stow/a/.config/nvim".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_action_rolls_back_the_whole_run() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "b/.vimrc"]);
+        write(stow_dir.join("b/syra.toml"), "[hooks]\npre-stow = 'touch \"$SYRA_TARGET_DIR/.vimrc\"'\n").unwrap();
+
+        let (result, report) = run_packages(&stow_dir, &target_dir, Operation::Stow, &["a", "b"], false);
+        let Err(Error::RolledBack(rollback)) = result else {
+            panic!("Expected the run to be rolled back");
+        };
+        assert_eq!(rollback.undone.len(), 1);
+        assert!(matches!(&rollback.error, Error::Io { package: Some(package), .. } if package == "b"));
+        assert!(!target_dir.join(".bashrc").exists());
+        assert!(report.executed.is_empty());
+    }
+}
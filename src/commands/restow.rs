@@ -0,0 +1,18 @@
+use crate::cli::{self, OutputFormat};
+use crate::error::Error;
+use crate::plan::Operation;
+
+use super::Completion;
+
+/// Brings the target in line with the current contents of the packages,
+/// without touching links that are still valid.
+pub fn run(args: cli::StowArgs, output: OutputFormat) -> Result<Completion, Error> {
+    if args.verbose && output == OutputFormat::Text {
+        println!(
+            "Restowing packages {:?}, src={:?}, dst={:?}",
+            args.packages, args.package_dir, args.target_dir
+        );
+    }
+
+    super::run_operation(output, Operation::Restow, args)
+}
@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+
+use crate::alternate::is_alternate;
+use crate::cli::{OutputFormat, StatusArgs};
+use crate::error::Error;
+use crate::fs::{InstalledItem, Package, Symlink, Target};
+use crate::report::{self, AlternateReport, ConflictReport, ItemReport, PackageReport, PackagesReport};
+use crate::state::State;
+use crate::status::package_status;
+
+use super::Context;
+
+/// Reports for each package whether it is stowed into the target.
+pub fn run(args: StatusArgs, output: OutputFormat) -> Result<(), Error> {
+    let mut report = PackagesReport::new("status");
+    let result = execute(args, output, &mut report);
+    if output == OutputFormat::Json {
+        report.error = result.as_ref().err().map(Error::to_string);
+        report::print(&report)?;
+    }
+    result
+}
+
+fn execute(args: StatusArgs, output: OutputFormat, report: &mut PackagesReport) -> Result<(), Error> {
+    let context = Context::new(args, output)?;
+    let state = State::load(context.target.path())?;
+
+    for package in context.packages()? {
+        let items = context.target.get_installed_package_contents(&package, &state)?;
+        let (status, conflicts) = package_status(&context.target, &state, &context.options, &package, &items)?;
+        let alternates = selected_alternates(&package)?;
+        if output == OutputFormat::Json {
+            report.packages.push(PackageReport {
+                name: package.name().to_string(),
+                status,
+                items: Some(items.iter().map(ItemReport::from).collect()),
+                conflicts: Some(conflicts.iter().map(ConflictReport::from).collect()),
+                alternates: Some(
+                    alternates
+                        .iter()
+                        .map(|(entry, path)| AlternateReport {
+                            entry: entry.to_string_lossy().into_owned(),
+                            path: path.to_string_lossy().into_owned(),
+                        })
+                        .collect(),
+                ),
+                error: None,
+            });
+            continue;
+        }
+
+        println!("{}: {}", package.name(), status);
+        if context.args.verbose {
+            for (entry, path) in &alternates {
+                println!("  alternate {} for {}", entry.display(), path.display());
+            }
+            for item in &items {
+                println!("  {}", describe(item));
+            }
+            for conflict in &conflicts {
+                println!("  conflict: {}", conflict);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the alternates selected in `package`, with the paths, relative to
+/// the target, that they are installed at.
+fn selected_alternates<P: Package>(package: &P) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    let mut alternates = Vec::new();
+    for entry in package.walk() {
+        let entry = entry?;
+        if is_alternate(&entry.path) {
+            let path = package.installed_path(&entry);
+            alternates.push((entry.path, path));
+        }
+    }
+    Ok(alternates)
+}
+
+fn describe(item: &InstalledItem) -> String {
+    match item {
+        InstalledItem::Item(Symlink { path, target }) => {
+            format!("owned    {} -> {}", path.display(), target.display())
+        }
+        InstalledItem::NotOwned(Symlink { path, target }) => {
+            format!("foreign  {} -> {}", path.display(), target.display())
+        }
+        InstalledItem::Installed(path) => format!("owned    {} (installed)", path.display()),
+        InstalledItem::Modified(path) => format!("modified {}", path.display()),
+        InstalledItem::Dir(path) => format!("dir      {}", path.display()),
+        InstalledItem::File(path) => format!("file     {}", path.display()),
+        InstalledItem::Missing(path) => format!("missing  {}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::tests::{create_files, setup};
+
+    #[test]
+    fn test_status_of_missing_package_fails() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc"]);
+        let args = StatusArgs {
+            package_dir: Some(stow_dir),
+            target_dir: Some(target_dir),
+            packages: vec!["a".to_string(), "nope".to_string()],
+            ..Default::default()
+        };
+
+        let mut report = PackagesReport::new("status");
+        let result = execute(args, OutputFormat::Json, &mut report);
+
+        assert!(matches!(result, Err(Error::PackageNotFound { .. })));
+        assert!(report.packages.is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+//! Default options read from configuration files.
+//!
+//! A configuration file is a TOML file named `.syrarc` or `syra.toml`, looked
+//! up in these directories, from highest to lowest precedence:
+//! - the current directory
+//! - the stow directory
+//! - `$XDG_CONFIG_HOME/syra` (`~/.config/syra` if `XDG_CONFIG_HOME` is not set)
+//!
+//! Within a directory `.syrarc` takes precedence over `syra.toml`. Options
+//! given on the command line always take precedence over all files, and
+//! `--no-verbose`, `--no-adopt` and `--no-dotfiles` turn off what a file
+//! turns on. The
+//! `ignore` patterns of all files and the command line are combined, and so
+//! are the template variables in `[vars]`, with the same precedence.
+//!
+//! ```toml
+//! dir = "~/dotfiles"
+//! target = "~"
+//! ignore = ['\.DS_Store', 'glob:*.orig']
+//! verbose = false
+//! adopt = false
+//! dotfiles = true
+//! link_mode = "relative"
+//! install_mode = "symlink"
+//! class = "work"
+//!
+//! [vars]
+//! email = "me@example.com"
+//! ```
+//!
+//! Relative paths are relative to the directory containing the file, and a
+//! leading `~` is replaced with the home directory. The stow directory itself
+//! is taken from the command line, the current directory's file or the XDG
+//! file, so `dir` is not used in the stow directory's file.
+
+use std::collections::BTreeMap;
+use std::env::{self, current_dir};
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::cli::StowArgs;
+use crate::error::{Error, IoAction, IoContext};
+use crate::fs::{InstallMode, LinkMode};
+
+pub const FILE_NAMES: &[&str] = &[".syrarc", "syra.toml"];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub dir: Option<PathBuf>,
+    pub target: Option<PathBuf>,
+    pub ignore: Vec<String>,
+    pub verbose: Option<bool>,
+    pub adopt: Option<bool>,
+    pub dotfiles: Option<bool>,
+    pub link_mode: Option<LinkMode>,
+    pub install_mode: Option<InstallMode>,
+    pub class: Option<String>,
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Reads the configuration file at `path`, or returns `None` if there is no such file.
+    pub fn read(path: &Path) -> Result<Option<Self>, Error> {
+        let contents = match read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::io(IoAction::Read, path, e)),
+        };
+        let mut config: Self =
+            toml::from_str(&contents).map_err(|e| Error::InvalidConfig(path.to_path_buf(), e.to_string()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        config.dir = config.dir.map(|dir| resolve(base, &dir));
+        config.target = config.target.map(|target| resolve(base, &target));
+        Ok(Some(config))
+    }
+
+    /// Reads the configuration files in `dir`, in order of precedence.
+    fn read_dir(dir: &Path) -> Result<Self, Error> {
+        let mut config = Self::default();
+        for name in FILE_NAMES {
+            if let Some(file) = Self::read(&dir.join(name))? {
+                config = config.or(file);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Finds and merges the configuration files for a run in `cwd`, with
+    /// `dir` being the stow directory given on the command line, if any.
+    pub fn discover(cwd: &Path, xdg_dir: Option<&Path>, dir: Option<&Path>) -> Result<Self, Error> {
+        let local = Self::read_dir(cwd)?;
+        let global = match xdg_dir {
+            Some(xdg_dir) => Self::read_dir(xdg_dir)?,
+            None => Self::default(),
+        };
+
+        let stow_dir = dir
+            .map(|dir| cwd.join(dir))
+            .or_else(|| local.dir.clone())
+            .or_else(|| global.dir.clone())
+            .unwrap_or_else(|| cwd.to_path_buf());
+        let stow = if stow_dir == cwd {
+            Self::default()
+        } else {
+            Self {
+                dir: None,
+                ..Self::read_dir(&stow_dir)?
+            }
+        };
+
+        Ok(local.or(stow).or(global))
+    }
+
+    /// Merges two configurations, preferring the values of `self`.
+    fn or(mut self, other: Self) -> Self {
+        self.ignore.extend(other.ignore);
+        let mut vars = other.vars;
+        vars.extend(self.vars);
+        Self {
+            dir: self.dir.or(other.dir),
+            target: self.target.or(other.target),
+            ignore: self.ignore,
+            verbose: self.verbose.or(other.verbose),
+            adopt: self.adopt.or(other.adopt),
+            dotfiles: self.dotfiles.or(other.dotfiles),
+            link_mode: self.link_mode.or(other.link_mode),
+            install_mode: self.install_mode.or(other.install_mode),
+            class: self.class.or(other.class),
+            vars,
+        }
+    }
+
+    /// Fills in the options that were not given on the command line.
+    pub fn apply(self, args: &mut StowArgs) {
+        args.package_dir = args.package_dir.take().or(self.dir);
+        args.target_dir = args.target_dir.take().or(self.target);
+        // A flag given either way wins over the configuration.
+        args.verbose = !args.no_verbose && (args.verbose || self.verbose.unwrap_or(false));
+        args.adopt = !args.no_adopt && (args.adopt || self.adopt.unwrap_or(false));
+        args.dotfiles = !args.no_dotfiles && (args.dotfiles || self.dotfiles.unwrap_or(false));
+        args.link_mode = args.link_mode.or(self.link_mode);
+        args.install_mode = args.install_mode.or(self.install_mode);
+        args.class = args.class.take().or(self.class);
+
+        let mut ignore = self.ignore;
+        ignore.append(&mut args.ignore);
+        args.ignore = ignore;
+
+        // Later variables override earlier ones, so the command line wins.
+        let mut vars: Vec<String> = self.vars.into_iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        vars.append(&mut args.vars);
+        args.vars = vars;
+    }
+}
+
+/// Returns `args` with the defaults from the configuration files applied.
+pub fn with_defaults(mut args: StowArgs) -> Result<StowArgs, Error> {
+    let cwd = current_dir().at(IoAction::CurrentDir, Path::new("."))?;
+    let config = Config::discover(&cwd, xdg_config_dir().as_deref(), args.package_dir.as_deref())?;
+    if args.verbose && !args.no_verbose {
+        println!("config: {:?}", config);
+    }
+    config.apply(&mut args);
+    Ok(args)
+}
+
+fn xdg_config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir()?.join(".config"),
+    };
+    Some(base.join("syra"))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME").filter(|home| !home.is_empty()).map(PathBuf::from)
+}
+
+/// Resolves a path from a configuration file in `base`.
+fn resolve(base: &Path, path: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix("~")
+        && let Some(home) = home_dir()
+    {
+        return home.join(rest);
+    }
+    base.join(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::fs::{create_dir_all, write};
+
+    fn args(cli: &[&str]) -> StowArgs {
+        StowArgs::parse_from(std::iter::once("syra").chain(cli.iter().copied()))
+    }
+
+    #[test]
+    fn test_read_resolves_relative_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        write(tmp.path().join(".syrarc"), "dir = \"dotfiles\"\ntarget = \"/home/user\"\n").unwrap();
+
+        let config = Config::read(&tmp.path().join(".syrarc")).unwrap().unwrap();
+
+        assert_eq!(config.dir, Some(tmp.path().join("dotfiles")));
+        assert_eq!(config.target, Some(PathBuf::from("/home/user")));
+    }
+
+    #[test]
+    fn test_read_rejects_unknown_keys() {
+        let tmp = tempfile::tempdir().unwrap();
+        write(tmp.path().join("syra.toml"), "targte = \"/home/user\"\n").unwrap();
+
+        assert!(matches!(
+            Config::read(&tmp.path().join("syra.toml")),
+            Err(Error::InvalidConfig(path, _)) if path == tmp.path().join("syra.toml")
+        ));
+    }
+
+    #[test]
+    fn test_discover_precedence() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cwd = tmp.path().join("cwd");
+        let stow_dir = tmp.path().join("stow");
+        let xdg_dir = tmp.path().join("xdg");
+        for dir in [&cwd, &stow_dir, &xdg_dir] {
+            create_dir_all(dir).unwrap();
+        }
+        write(cwd.join(".syrarc"), "ignore = ['cwd']\nverbose = true\n").unwrap();
+        write(cwd.join("syra.toml"), "ignore = ['cwd-toml']\nverbose = false\nadopt = true\n").unwrap();
+        write(stow_dir.join("syra.toml"), "ignore = ['stow']\ntarget = 'target'\n").unwrap();
+        write(
+            xdg_dir.join("syra.toml"),
+            "ignore = ['xdg']\ndir = '../stow'\ntarget = '/xdg'\ndotfiles = true\nlink_mode = 'absolute'\n",
+        )
+        .unwrap();
+
+        let config = Config::discover(&cwd, Some(&xdg_dir), None).unwrap();
+
+        assert_eq!(config.dir, Some(xdg_dir.join("../stow")));
+        assert_eq!(config.target, Some(xdg_dir.join("../stow").join("target")));
+        assert_eq!(config.ignore, vec!["cwd", "cwd-toml", "stow", "xdg"]);
+        assert_eq!(config.verbose, Some(true));
+        assert_eq!(config.adopt, Some(true));
+        assert_eq!(config.dotfiles, Some(true));
+        assert_eq!(config.link_mode, Some(LinkMode::Absolute));
+    }
+
+    #[test]
+    fn test_command_line_wins() {
+        let config = Config {
+            dir: Some(PathBuf::from("/config/dir")),
+            target: Some(PathBuf::from("/config/target")),
+            ignore: vec!["config".to_string()],
+            verbose: Some(true),
+            adopt: None,
+            dotfiles: Some(true),
+            link_mode: Some(LinkMode::Absolute),
+            install_mode: Some(InstallMode::Copy),
+            class: Some("work".to_string()),
+            vars: BTreeMap::from([("email".to_string(), "config".to_string())]),
+        };
+        let mut args = args(&["-t", "/cli/target", "--ignore", "cli", "--link-mode", "relative", "--var", "email=cli", "pkg"]);
+
+        config.apply(&mut args);
+
+        assert_eq!(args.package_dir, Some(PathBuf::from("/config/dir")));
+        assert_eq!(args.target_dir, Some(PathBuf::from("/cli/target")));
+        assert_eq!(args.ignore, vec!["config", "cli"]);
+        assert!(args.verbose);
+        assert!(!args.adopt);
+        assert!(args.dotfiles);
+        assert_eq!(args.link_mode, Some(LinkMode::Relative));
+        assert_eq!(args.install_mode, Some(InstallMode::Copy));
+        assert_eq!(args.vars, vec!["email=config", "email=cli"]);
+        assert_eq!(args.class.as_deref(), Some("work"));
+
+        // Options turned on in the configuration can be turned off again.
+        let config = Config {
+            verbose: Some(true),
+            adopt: Some(true),
+            dotfiles: Some(true),
+            ..Default::default()
+        };
+        let mut off = self::args(&["--no-verbose", "--no-adopt", "--dotfiles", "--no-dotfiles", "pkg"]);
+        config.apply(&mut off);
+        assert!(!off.verbose);
+        assert!(!off.adopt);
+        assert!(!off.dotfiles);
+
+        let config = Config {
+            adopt: Some(false),
+            ..Default::default()
+        };
+        let mut on = self::args(&["--no-adopt", "--adopt", "pkg"]);
+        config.apply(&mut on);
+        assert!(on.adopt);
+    }
+}
@@ -0,0 +1,196 @@
+//! Links into the stow directory that are not links syra would create.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::alternate::{base_path, Machine};
+use crate::error::{Error, IoAction, IoContext};
+use crate::fs::{dotfiles_path, resolve_link, LinkMode, Package, PackageImpl, PathState, Symlink};
+use crate::plan::Options;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// The link points at something that does not exist.
+    Dangling,
+    /// The link points at a package entry that is ignored, or at an
+    /// alternate that is not selected.
+    Ignored,
+    /// No package links the entry it points at to where the link is.
+    Unclaimed,
+    /// The link is absolute, while syra creates relative links.
+    Absolute,
+    /// The link is relative, while syra creates absolute links.
+    Relative,
+}
+
+impl Problem {
+    /// Returns true if `--fix` removes links with this problem.
+    pub fn is_fixable(&self) -> bool {
+        matches!(self, Problem::Dangling | Problem::Ignored)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Dangling => write!(f, "dangling"),
+            Problem::Ignored => write!(f, "ignored"),
+            Problem::Unclaimed => write!(f, "unclaimed"),
+            Problem::Absolute => write!(f, "absolute"),
+            Problem::Relative => write!(f, "relative"),
+        }
+    }
+}
+
+pub struct Finding {
+    pub link: Symlink,
+    pub problem: Problem,
+}
+
+/// Walks `target_dir` and returns the problems with the links into `stow_dir`.
+///
+/// Links to directories are not followed, and the stow directory itself is
+/// skipped if it is inside the target.
+pub fn find_problems(target_dir: &Path, stow_dir: &Path, options: &Options) -> Result<Vec<Finding>, Error> {
+    let mut findings = Vec::new();
+    let mut dirs = vec![target_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                options.log.write(format_args!("doctor: skipping {:?}: {}", dir, e));
+                continue;
+            }
+            Err(e) => return Err(Error::io(IoAction::ReadDir, &dir, e)),
+        };
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            paths.push(entry.at(IoAction::ReadDir, &dir)?.path());
+        }
+        paths.sort();
+
+        for path in paths {
+            match PathState::of(&path)? {
+                PathState::Dir if path != stow_dir => dirs.push(path),
+                PathState::Symlink(target) => {
+                    if let Some(problem) = examine(target_dir, stow_dir, options, &path, &target)? {
+                        findings.push(Finding {
+                            link: Symlink { path, target },
+                            problem,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    findings.sort_by(|a, b| a.link.path.cmp(&b.link.path));
+    Ok(findings)
+}
+
+/// Returns the problem with the link at `path`, if it points into the stow directory.
+fn examine(
+    target_dir: &Path,
+    stow_dir: &Path,
+    options: &Options,
+    path: &Path,
+    link_target: &Path,
+) -> Result<Option<Problem>, Error> {
+    let resolved = resolve_link(path, link_target);
+    if !resolved.starts_with(stow_dir) {
+        return Ok(None);
+    }
+    if PathState::of(&resolved)? == PathState::Missing {
+        return Ok(Some(Problem::Dangling));
+    }
+
+    let machine = Machine::of(&options.variables);
+    let Some((package, item)) = PackageImpl::containing(stow_dir, &resolved, &options.ignore, &machine) else {
+        return Ok(Some(Problem::Unclaimed));
+    };
+    if item.as_os_str().is_empty() || package.name().starts_with('.') {
+        return Ok(Some(Problem::Unclaimed));
+    }
+    if item.ancestors().any(|ancestor| !ancestor.as_os_str().is_empty() && package.is_ignored(ancestor)) {
+        return Ok(Some(Problem::Ignored));
+    }
+
+    let installed = base_path(&item);
+    let expected = if options.dotfiles {
+        target_dir.join(dotfiles_path(&installed))
+    } else {
+        target_dir.join(&installed)
+    };
+    if expected != path {
+        return Ok(Some(Problem::Unclaimed));
+    }
+    match options.link_mode {
+        LinkMode::Relative if link_target.is_absolute() => return Ok(Some(Problem::Absolute)),
+        LinkMode::Absolute if !link_target.is_absolute() => return Ok(Some(Problem::Relative)),
+        _ => {}
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::symlink;
+    use crate::plan::tests::{create_files, setup};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_find_problems() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.profile", "a/.vimrc", "a/.bashrc~", "a/.config/app/rc"]);
+        create_files(&target_dir, &[".config/other/file"]);
+        symlink("../stow/a/.bashrc", target_dir.join(".bashrc")).unwrap();
+        symlink("../stow/a/.removed", target_dir.join(".removed")).unwrap();
+        symlink("../stow/a/.bashrc~", target_dir.join(".bashrc~")).unwrap();
+        symlink(stow_dir.join("a/.profile"), target_dir.join(".profile")).unwrap();
+        symlink("../stow/a/.vimrc", target_dir.join(".exrc")).unwrap();
+        symlink("../../stow/a/.config/app", target_dir.join(".config/app")).unwrap();
+        symlink("/etc/hosts", target_dir.join(".config/other/hosts")).unwrap();
+
+        let findings = find_problems(&target_dir, &stow_dir, &Options::default()).unwrap();
+
+        let found: Vec<(PathBuf, Problem)> = findings
+            .into_iter()
+            .map(|finding| (finding.link.path, finding.problem))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (target_dir.join(".bashrc~"), Problem::Ignored),
+                (target_dir.join(".exrc"), Problem::Unclaimed),
+                (target_dir.join(".profile"), Problem::Absolute),
+                (target_dir.join(".removed"), Problem::Dangling),
+            ]
+        );
+
+        let absolute = Options {
+            link_mode: LinkMode::Absolute,
+            ..Default::default()
+        };
+        let found: Vec<(PathBuf, Problem)> = find_problems(&target_dir, &stow_dir, &absolute)
+            .unwrap()
+            .into_iter()
+            .filter(|finding| matches!(finding.problem, Problem::Absolute | Problem::Relative))
+            .map(|finding| (finding.link.path, finding.problem))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (target_dir.join(".bashrc"), Problem::Relative),
+                (target_dir.join(".config/app"), Problem::Relative),
+            ]
+        );
+    }
+}
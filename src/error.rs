@@ -1,35 +1,235 @@
-use std::{io, path::PathBuf};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::exec::Rollback;
+
+/// The file system operation that failed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoAction {
+    CurrentDir,
+    Canonicalize,
+    Metadata,
+    ReadDir,
+    ReadLink,
+    Read,
+    Write,
+    CreateDir,
+    RemoveDir,
+    Symlink,
+    Unlink,
+    Rename,
+    Copy,
+    HardLink,
+}
+
+impl fmt::Display for IoAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IoAction::CurrentDir => "current_dir",
+            IoAction::Canonicalize => "canonicalize",
+            IoAction::Metadata => "metadata",
+            IoAction::ReadDir => "read_dir",
+            IoAction::ReadLink => "read_link",
+            IoAction::Read => "read",
+            IoAction::Write => "write",
+            IoAction::CreateDir => "create_dir",
+            IoAction::RemoveDir => "remove_dir",
+            IoAction::Symlink => "symlink",
+            IoAction::Unlink => "unlink",
+            IoAction::Rename => "rename",
+            IoAction::Copy => "copy",
+            IoAction::HardLink => "hard_link",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 pub enum Error
 {
-    Io(io::Error),
-    PathNotAbsolute,
+    /// An I/O operation on `path` failed, while processing `package` if it
+    /// is known.
+    Io {
+        action: IoAction,
+        path: PathBuf,
+        package: Option<String>,
+        source: io::Error,
+    },
+    PathNotAbsolute(PathBuf),
     DefaultTargetNotAvailable,
     MissingPackages,
-    LinkPathExists(PathBuf),
+    PackageNotFound { name: String, dir: PathBuf },
+    LinkPathExists(PathBuf, String),
     LinkNotOwnedByPackage(PathBuf, String),
+    Conflicts(Vec<Error>),
+    RolledBack(Box<Rollback>),
+    InvalidIgnorePattern(String, String),
+    InvalidConfig(PathBuf, String),
+    HardlinkAcrossFileSystems { package: PathBuf, target: PathBuf },
+    InvalidVariable(String),
+    Template(PathBuf, String),
+    DependencyCycle(Vec<String>),
+    PackageConflict { package: String, other: String },
+    PackageNeeded { package: String, by: String },
+    Hook { package: String, hook: &'static str, reason: String },
+}
+
+impl Error {
+    pub fn io(action: IoAction, path: &Path, source: io::Error) -> Self {
+        Error::Io {
+            action,
+            path: path.to_path_buf(),
+            package: None,
+            source,
+        }
+    }
+
+    /// Records `name` as the package that was being processed when an I/O
+    /// error occurred, unless one is recorded already. For a rollback, the
+    /// error that caused it is annotated.
+    pub fn in_package(mut self, name: &str) -> Self {
+        self.set_package(name);
+        self
+    }
+
+    fn set_package(&mut self, name: &str) {
+        match self {
+            Error::Io { package: package @ None, .. } => *package = Some(name.to_string()),
+            Error::RolledBack(rollback) => rollback.error.set_package(name),
+            _ => {}
+        }
+    }
+}
+
+/// Adds the failed action and its path to I/O errors.
+pub trait IoContext<T> {
+    fn at(self, action: IoAction, path: &Path) -> Result<T, Error>;
 }
 
-impl From<io::Error> for Error {
-    fn from(err: io::Error) -> Self {
-        Error::Io(err)
+impl<T> IoContext<T> for Result<T, io::Error> {
+    fn at(self, action: IoAction, path: &Path) -> Result<T, Error> {
+        self.map_err(|e| Error::io(action, path, e))
     }
 }
 
-impl std::fmt::Debug for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::Io(e) => write!(f, "IO error: {}", e),
-            Error::PathNotAbsolute => write!(f, "Path is not absolute"),
+            Error::Io {
+                action,
+                path,
+                package,
+                source,
+            } => {
+                write!(f, "{} '{}' failed", action, path.display())?;
+                if let Some(package) = package {
+                    write!(f, " for package '{}'", package)?;
+                }
+                write!(f, ": {}", source)
+            }
+            Error::PathNotAbsolute(path) => write!(f, "Path '{}' is not absolute", path.display()),
             Error::DefaultTargetNotAvailable => write!(f, "Default target directory is not available"),
             Error::MissingPackages => write!(f, "At least one package is required"),
-            Error::LinkPathExists(path) => write!(f, "Link path already exists: {}", path.display()),
+            Error::PackageNotFound { name, dir } => {
+                write!(f, "Package '{}' not found in '{}'", name, dir.display())
+            }
+            Error::LinkPathExists(path, pkg) => write!(
+                f,
+                "Link path '{}' for package '{}' already exists",
+                path.display(),
+                pkg
+            ),
             Error::LinkNotOwnedByPackage(path, pkg) => write!(
                 f,
                 "Link path '{}' is not owned by package '{}'",
                 path.display(),
                 pkg
             ),
+            Error::Conflicts(conflicts) => {
+                write!(f, "{} conflict(s) found", conflicts.len())?;
+                for conflict in conflicts {
+                    write!(f, "\n  {}", conflict)?;
+                }
+                Ok(())
+            }
+            Error::InvalidIgnorePattern(pattern, reason) => {
+                write!(f, "Invalid ignore pattern '{}': {}", pattern, reason)
+            }
+            Error::InvalidConfig(path, reason) => {
+                write!(f, "Invalid configuration file '{}': {}", path.display(), reason)
+            }
+            Error::HardlinkAcrossFileSystems { package, target } => write!(
+                f,
+                "Cannot hard link package '{}' into '{}': they are on different file systems, use --install-mode copy instead",
+                package.display(),
+                target.display()
+            ),
+            Error::InvalidVariable(var) => write!(f, "Invalid variable '{}', expected NAME=VALUE", var),
+            Error::Template(path, reason) => write!(f, "Failed to render template '{}': {}", path.display(), reason),
+            Error::DependencyCycle(cycle) => write!(f, "Dependency cycle between packages: {}", cycle.join(" -> ")),
+            Error::PackageConflict { package, other } => {
+                write!(f, "Package '{}' conflicts with package '{}'", package, other)
+            }
+            Error::PackageNeeded { package, by } => write!(
+                f,
+                "Package '{}' is needed by stowed package '{}', use --force to unstow it anyway",
+                package, by
+            ),
+            Error::Hook { package, hook, reason } => {
+                write!(f, "Hook {} of package '{}' failed: {}", hook, package, reason)
+            }
+            Error::RolledBack(rollback) => {
+                write!(f, "{}", rollback.error)?;
+                write!(f, "\nrolled back {} action(s):", rollback.undone.len())?;
+                for action in &rollback.undone {
+                    write!(f, "\n  {}", action)?;
+                }
+                if !rollback.undo_failures.is_empty() {
+                    write!(f, "\nfailed to roll back {} action(s), the target is partially modified:", rollback.undo_failures.len())?;
+                    for (_, error) in &rollback.undo_failures {
+                        write!(f, "\n  {}", error)?;
+                    }
+                }
+                Ok(())
+            }
         }
     }
 }
+
+// Errors end up in `unwrap` and `{:?}` output, where the message is more
+// useful than the structure.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::RolledBack(rollback) => Some(&rollback.error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_io_error_context() {
+        let error = std::fs::read_dir("/nonexistent/stow/a")
+            .at(IoAction::ReadDir, Path::new("/nonexistent/stow/a"))
+            .map_err(|e| e.in_package("a"))
+            .map_err(|e| e.in_package("b"))
+            .unwrap_err();
+
+        assert!(matches!(&error, Error::Io { action: IoAction::ReadDir, package: Some(package), .. } if package == "a"));
+        assert!(error.to_string().starts_with("read_dir '/nonexistent/stow/a' failed for package 'a': "));
+        let source = error.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+    }
+}
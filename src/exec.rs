@@ -0,0 +1,271 @@
+//! Execution of plans.
+//!
+//! Every action that is executed is recorded in a journal together with the
+//! action that undoes it. If an action fails, the journal is replayed in
+//! reverse so that the target is returned to the state it was in before the
+//! run.
+
+use std::fs::{read_link, remove_file};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, IoAction, IoContext};
+use crate::fs::{Action, Symlink};
+use crate::log::Log;
+use crate::state::State;
+
+/// What happened when executing a plan failed.
+pub struct Rollback {
+    /// The action that failed, or `None` if what failed was run after all
+    /// actions were executed, and why.
+    pub failed: Option<Action>,
+    pub error: Error,
+    /// The actions that were executed to undo the changes made before the
+    /// failure, in the order they were executed.
+    pub undone: Vec<Action>,
+    /// Undo actions that failed as well. If this is not empty, the target is
+    /// left in a partially modified state.
+    pub undo_failures: Vec<(Action, Error)>,
+}
+
+pub struct Executor<'a> {
+    state: &'a mut State,
+    log: Log,
+    journal: Vec<Action>,
+    /// Package files replaced by adopted files, and installed files that
+    /// were removed, kept until the run succeeds, along with the length of
+    /// the journal when they were made.
+    backups: Vec<(usize, PathBuf)>,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new(state: &'a mut State, log: Log) -> Self {
+        Self {
+            state,
+            log,
+            journal: Vec::new(),
+            backups: Vec::new(),
+        }
+    }
+
+    /// Executes `actions` in order. If one of them fails, the ones executed
+    /// before it are undone and the returned error describes the rollback.
+    pub fn execute(mut self, actions: &[Action]) -> Result<(), Error> {
+        self.run(actions)?;
+        self.finish()
+    }
+
+    /// Executes `actions` in order, after the ones executed so far. If one
+    /// of them fails, everything this executor executed is undone and the
+    /// returned error describes the rollback.
+    pub fn run(&mut self, actions: &[Action]) -> Result<(), Error> {
+        for action in actions {
+            // Some actions are executed in steps, so that a failure halfway
+            // through can be undone as well.
+            let steps = match action {
+                Action::Unfold(path) => vec![Action::Unlink(path.clone()), Action::CreateDir(path.clone())],
+                Action::Adopt(Symlink { path, target }) => {
+                    let backup = backup_of(target);
+                    self.backups.push((self.journal.len(), backup.clone()));
+                    vec![
+                        Action::Rename {
+                            from: target.clone(),
+                            to: backup,
+                        },
+                        Action::Rename {
+                            from: path.clone(),
+                            to: target.clone(),
+                        },
+                    ]
+                }
+                Action::RemoveFile(path) => {
+                    // Kept outside of the file's directory, which may be
+                    // removed next.
+                    let backup = self.state.target_dir().join(format!(".syra-backup-{}", self.backups.len()));
+                    self.backups.push((self.journal.len(), backup.clone()));
+                    vec![Action::Rename {
+                        from: path.clone(),
+                        to: backup,
+                    }]
+                }
+                _ => vec![action.clone()],
+            };
+            for step in steps {
+                if let Err(error) = self.apply(&step) {
+                    return Err(self.roll_back_to(0, Some(step), error));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a mark that `roll_back_to` can undo the actions executed after.
+    pub fn mark(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Undoes the actions executed since `mark` was taken, because of
+    /// `error`, and returns the error describing the rollback. `failed` is the
+    /// action that failed, if an action did.
+    pub fn roll_back_to(&mut self, mark: usize, failed: Option<Action>, error: Error) -> Error {
+        let count = self.journal.len().saturating_sub(mark);
+        match &failed {
+            Some(failed) => self.log.write(format_args!("Executing {} failed, rolling back {} action(s)", failed, count)),
+            None => self.log.write(format_args!("{}, rolling back {} action(s)", error, count)),
+        }
+
+        let mut undone = Vec::new();
+        let mut undo_failures = Vec::new();
+        while self.journal.len() > mark
+            && let Some(undo) = self.journal.pop()
+        {
+            self.log.write(format_args!("Undoing: {}", undo));
+            match undo.execute().and_then(|()| self.state.record(&undo)) {
+                Ok(()) => undone.push(undo),
+                Err(e) => undo_failures.push((undo, e)),
+            }
+        }
+        // The backups made since the mark were moved back.
+        self.backups.retain(|(made_at, _)| *made_at < mark);
+
+        Error::RolledBack(Box::new(Rollback {
+            failed,
+            error,
+            undone,
+            undo_failures,
+        }))
+    }
+
+    /// Removes the backups kept in case the run failed.
+    pub fn finish(self) -> Result<(), Error> {
+        for (_, backup) in &self.backups {
+            self.log.write(format_args!("Removing backup {:?}", backup));
+            remove_file(backup).at(IoAction::Unlink, backup)?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single action and records the action that undoes it.
+    fn apply(&mut self, action: &Action) -> Result<(), Error> {
+        self.log.write(format_args!("Executing: {}", action));
+        let undo = undo_of(action)?;
+        action.execute()?;
+        self.journal.push(undo);
+        self.state.record(action)
+    }
+}
+
+/// Returns the action that reverts `action`, based on the current contents of the target.
+fn undo_of(action: &Action) -> Result<Action, Error> {
+    Ok(match action {
+        Action::CreateDir(path) => Action::RemoveDir(path.clone()),
+        Action::Link(Symlink { path, .. }) => Action::Unlink(path.clone()),
+        Action::Unlink(path) => Action::Link(Symlink {
+            path: path.clone(),
+            target: read_link(path).at(IoAction::ReadLink, path)?,
+        }),
+        Action::RemoveDir(path) => Action::CreateDir(path.clone()),
+        Action::Rename { from, to } => Action::Rename {
+            from: to.clone(),
+            to: from.clone(),
+        },
+        Action::Copy { path, .. } | Action::Hardlink { path, .. } | Action::Render { path, .. } => {
+            Action::Unlink(path.clone())
+        }
+        Action::Unfold(path) | Action::Adopt(Symlink { path, .. }) | Action::RemoveFile(path) => {
+            unreachable!("{:?} is executed in steps", path)
+        }
+    })
+}
+
+/// Returns the path that the file at `path` is moved to until a run succeeds.
+fn backup_of(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_os_string();
+    backup.push(".syra-backup");
+    PathBuf::from(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::symlink;
+    use std::fs::{create_dir_all, read_to_string, write};
+
+    fn link(path: PathBuf, target: &str) -> Action {
+        Action::Link(Symlink {
+            path,
+            target: PathBuf::from(target),
+        })
+    }
+
+    #[test]
+    fn test_execute_applies_all_actions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target_dir = tmp.path();
+        let mut state = State::load(target_dir).unwrap();
+
+        let actions = vec![Action::CreateDir(target_dir.join("dir")), link(target_dir.join("dir/a"), "../a")];
+        Executor::new(&mut state, Log::default()).execute(&actions).unwrap();
+
+        assert!(target_dir.join("dir").is_dir());
+        assert_eq!(read_link(target_dir.join("dir/a")).unwrap(), PathBuf::from("../a"));
+        assert!(state.is_created_dir(&target_dir.join("dir")));
+    }
+
+    #[test]
+    fn test_failure_rolls_back_executed_actions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target_dir = tmp.path();
+        create_dir_all(target_dir.join("existing")).unwrap();
+        symlink("stow/a/.config", target_dir.join(".config")).unwrap();
+        symlink("stow/a/.old", target_dir.join(".old")).unwrap();
+        let mut state = State::load(target_dir).unwrap();
+
+        let actions = vec![
+            link(target_dir.join(".bashrc"), "stow/a/.bashrc"),
+            Action::Unlink(target_dir.join(".old")),
+            Action::Unfold(target_dir.join(".config")),
+            link(target_dir.join(".config/nvim"), "../stow/a/.config/nvim"),
+            Action::CreateDir(target_dir.join("existing")),
+            link(target_dir.join(".profile"), "stow/a/.profile"),
+        ];
+        let result = Executor::new(&mut state, Log::default()).execute(&actions);
+
+        let Err(Error::RolledBack(rollback)) = result else {
+            panic!("Expected the execution to be rolled back");
+        };
+        assert!(matches!(&rollback.failed, Some(Action::CreateDir(path)) if path == &target_dir.join("existing")));
+        assert_eq!(rollback.undone.len(), 5);
+        assert!(rollback.undo_failures.is_empty());
+
+        assert!(!target_dir.join(".bashrc").is_symlink());
+        assert!(!target_dir.join(".profile").is_symlink());
+        assert_eq!(read_link(target_dir.join(".old")).unwrap(), PathBuf::from("stow/a/.old"));
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), PathBuf::from("stow/a/.config"));
+        assert!(!state.is_created_dir(&target_dir.join(".config")));
+    }
+
+    #[test]
+    fn test_failed_adopt_restores_both_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target_dir = tmp.path();
+        create_dir_all(target_dir.join("stow/a")).unwrap();
+        write(target_dir.join("stow/a/.bashrc"), "package").unwrap();
+        write(target_dir.join(".bashrc"), "machine").unwrap();
+        let mut state = State::load(target_dir).unwrap();
+
+        let actions = vec![
+            Action::Adopt(Symlink {
+                path: target_dir.join(".bashrc"),
+                target: target_dir.join("stow/a/.bashrc"),
+            }),
+            link(target_dir.join(".bashrc"), "stow/a/.bashrc"),
+            Action::CreateDir(target_dir.join("stow")),
+        ];
+        let result = Executor::new(&mut state, Log::default()).execute(&actions);
+
+        assert!(matches!(result, Err(Error::RolledBack(_))));
+        assert_eq!(read_to_string(target_dir.join(".bashrc")).unwrap(), "machine");
+        assert_eq!(read_to_string(target_dir.join("stow/a/.bashrc")).unwrap(), "package");
+        assert_eq!(target_dir.join("stow/a").read_dir().unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,76 @@
+//! Exit codes.
+//!
+//! | code | meaning                                                         |
+//! |------|-----------------------------------------------------------------|
+//! | 0    | success                                                         |
+//! | 1    | an I/O error or a failed hook, including a rolled back run      |
+//! | 2    | a usage error: invalid arguments, patterns, config or templates |
+//! | 3    | conflicts with the target or between packages, nothing changed  |
+//! | 4    | no package was given, or a package does not exist               |
+//! | 5    | run with `-n`, and there are changes to make                    |
+
+use crate::error::Error;
+
+pub const SUCCESS: u8 = 0;
+pub const IO: u8 = 1;
+/// The same code clap exits with when it rejects the command line.
+pub const USAGE: u8 = 2;
+pub const CONFLICTS: u8 = 3;
+pub const MISSING_PACKAGE: u8 = 4;
+pub const CHANGES_PENDING: u8 = 5;
+
+/// The exit codes, as shown at the end of `--help`.
+pub const HELP: &str = "Exit codes:
+  0  success
+  1  I/O error or failed hook, including a run that was rolled back
+  2  usage error: invalid arguments, patterns, configuration or templates
+  3  conflicts with the target or between packages, nothing changed
+  4  no package was given, or a package does not exist
+  5  run with -n, and there are changes to make";
+
+impl Error {
+    /// Returns the code syra exits with when it fails with this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io { .. } | Error::RolledBack(_) | Error::Hook { .. } => IO,
+            Error::PathNotAbsolute(_)
+            | Error::DefaultTargetNotAvailable
+            | Error::InvalidIgnorePattern(..)
+            | Error::InvalidConfig(..)
+            | Error::HardlinkAcrossFileSystems { .. }
+            | Error::InvalidVariable(_)
+            | Error::Template(..)
+            | Error::DependencyCycle(_) => USAGE,
+            Error::LinkPathExists(..)
+            | Error::LinkNotOwnedByPackage(..)
+            | Error::PackageConflict { .. }
+            | Error::PackageNeeded { .. }
+            | Error::Conflicts(_) => CONFLICTS,
+            Error::MissingPackages | Error::PackageNotFound { .. } => MISSING_PACKAGE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use crate::error::IoAction;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_exit_code() {
+        let conflict = Error::LinkPathExists(PathBuf::from("/target/.bashrc"), "a".to_string());
+        assert_eq!(conflict.exit_code(), CONFLICTS);
+        assert_eq!(Error::Conflicts(vec![conflict]).exit_code(), CONFLICTS);
+        let io_error = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(Error::io(IoAction::ReadDir, Path::new("/stow/a"), io_error).exit_code(), IO);
+        assert_eq!(Error::InvalidIgnorePattern("(".to_string(), "unclosed group".to_string()).exit_code(), USAGE);
+        assert_eq!(Error::MissingPackages.exit_code(), MISSING_PACKAGE);
+        let not_found = Error::PackageNotFound {
+            name: "a".to_string(),
+            dir: PathBuf::from("/stow"),
+        };
+        assert_eq!(not_found.exit_code(), MISSING_PACKAGE);
+    }
+}
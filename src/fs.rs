@@ -1,13 +1,126 @@
-use std::{io, path};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{read_link, symlink_metadata};
+use std::io;
 use std::path::{Component, Path, PathBuf};
 
-use crate::error::Error;
+use crate::alternate::{self, base_path, Machine};
+use crate::error::{Error, IoAction, IoContext};
+use crate::ignore::IgnoreList;
+use crate::manifest::Manifest;
+use crate::state::{FileOrigin, State};
+use crate::template::{has_template_suffix, rendered_path, PACKAGE_TEMPLATES_FILE};
 
+#[derive(Clone)]
 pub struct Symlink {
     pub path: PathBuf,
     pub target: PathBuf,
 }
 
+/// A single modification of the target directory.
+#[derive(Clone)]
+pub enum Action {
+    CreateDir(PathBuf),
+    Link(Symlink),
+    Unlink(PathBuf),
+    RemoveDir(PathBuf),
+    /// Replaces a directory symlink with an empty directory. The entries of
+    /// the directory it pointed to are linked by separate `Link` actions.
+    Unfold(PathBuf),
+    /// Moves the file at `path` into the package, replacing `target`.
+    Adopt(Symlink),
+    Rename { from: PathBuf, to: PathBuf },
+    /// Copies the package entry `source` to `path`.
+    Copy { source: PathBuf, path: PathBuf },
+    /// Creates a hard link to the package entry `source` at `path`.
+    Hardlink { source: PathBuf, path: PathBuf },
+    /// Removes a file that syra installed by copying, hard linking or
+    /// rendering.
+    RemoveFile(PathBuf),
+    /// Writes `content`, rendered from the template `source`, to `path`.
+    Render { source: PathBuf, path: PathBuf, content: String },
+}
+
+impl Action {
+    /// Returns the path in the target that the action changes.
+    pub fn path(&self) -> &Path {
+        match self {
+            Action::CreateDir(path)
+            | Action::Link(Symlink { path, .. })
+            | Action::Unlink(path)
+            | Action::RemoveDir(path)
+            | Action::Unfold(path)
+            | Action::Adopt(Symlink { path, .. })
+            | Action::Rename { from: path, .. }
+            | Action::Copy { path, .. }
+            | Action::Hardlink { path, .. }
+            | Action::RemoveFile(path)
+            | Action::Render { path, .. } => path,
+        }
+    }
+
+    pub fn execute(&self) -> Result<(), Error> {
+        match self {
+            Action::CreateDir(path) => std::fs::create_dir(path).at(IoAction::CreateDir, path),
+            Action::Link(Symlink { path, target }) => symlink(target, path).at(IoAction::Symlink, path),
+            Action::Unlink(path) => std::fs::remove_file(path).at(IoAction::Unlink, path),
+            Action::RemoveDir(path) => std::fs::remove_dir(path).at(IoAction::RemoveDir, path),
+            Action::Unfold(path) => {
+                std::fs::remove_file(path).at(IoAction::Unlink, path)?;
+                std::fs::create_dir(path).at(IoAction::CreateDir, path)
+            }
+            Action::Adopt(Symlink { path, target }) => rename(path, target).at(IoAction::Rename, path),
+            Action::Rename { from, to } => rename(from, to).at(IoAction::Rename, from),
+            Action::Copy { source, path } => copy_file(source, path).at(IoAction::Copy, path),
+            Action::Hardlink { source, path } => std::fs::hard_link(source, path).at(IoAction::HardLink, path),
+            Action::RemoveFile(path) => std::fs::remove_file(path).at(IoAction::Unlink, path),
+            Action::Render { source, path, content } => write_file(source, path, content).at(IoAction::Write, path),
+        }
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::CreateDir(path) => write!(f, "mkdir({:?})", path),
+            Action::Link(Symlink { path, target }) => write!(f, "symlink({:?}, {:?})", path, target),
+            Action::Unlink(path) => write!(f, "unlink({:?})", path),
+            Action::RemoveDir(path) => write!(f, "rmdir({:?})", path),
+            Action::Unfold(path) => write!(f, "unfold({:?})", path),
+            Action::Adopt(Symlink { path, target }) => write!(f, "adopt({:?}, {:?})", path, target),
+            Action::Rename { from, to } => write!(f, "rename({:?}, {:?})", from, to),
+            Action::Copy { source, path } => write!(f, "copy({:?}, {:?})", path, source),
+            Action::Hardlink { source, path } => write!(f, "hardlink({:?}, {:?})", path, source),
+            Action::RemoveFile(path) => write!(f, "rm({:?})", path),
+            Action::Render { source, path, .. } => write!(f, "render({:?}, {:?})", path, source),
+        }
+    }
+}
+
+/// What currently occupies a path in the target directory.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathState {
+    Missing,
+    Dir,
+    Symlink(PathBuf),
+    File,
+}
+
+impl PathState {
+    /// Inspects `path` without following a symlink at its last component.
+    pub fn of(path: &Path) -> Result<Self, Error> {
+        match symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                Ok(PathState::Symlink(read_link(path).at(IoAction::ReadLink, path)?))
+            }
+            Ok(meta) if meta.is_dir() => Ok(PathState::Dir),
+            Ok(_) => Ok(PathState::File),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PathState::Missing),
+            Err(e) => Err(Error::io(IoAction::Metadata, path, e)),
+        }
+    }
+}
+
 pub struct BasePath<'a>(pub &'a Path);
 pub struct TargetPath<'a>(pub &'a Path);
 
@@ -21,8 +134,11 @@ pub fn relative_path(target: TargetPath, base: BasePath) -> Result<PathBuf, Erro
     let BasePath(base) = base;
     let TargetPath(target) = target;
 
-    if !target.is_absolute() || !base.is_absolute() {
-        return Err(Error::PathNotAbsolute);
+    if !target.is_absolute() {
+        return Err(Error::PathNotAbsolute(target.to_path_buf()));
+    }
+    if !base.is_absolute() {
+        return Err(Error::PathNotAbsolute(base.to_path_buf()));
     }
 
     let target_components: Vec<_> = target.components().collect();
@@ -49,6 +165,62 @@ pub fn relative_path(target: TargetPath, base: BasePath) -> Result<PathBuf, Erro
     Ok(result)
 }
 
+/// Returns `item` with every component starting with `dot-` renamed to start
+/// with `.` instead, e.g. `dot-config/dot-app` becomes `.config/.app`.
+pub fn dotfiles_path(item: &Path) -> PathBuf {
+    item.components()
+        .map(|component| match component.as_os_str().to_str() {
+            Some(name) if name.len() > "dot-".len() && name.starts_with("dot-") => {
+                PathBuf::from(format!(".{}", &name["dot-".len()..]))
+            }
+            _ => PathBuf::from(component.as_os_str()),
+        })
+        .collect()
+}
+
+/// How the targets of the links that syra creates are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// Relative to the directory containing the link, so that the target
+    /// and stow directories can be moved together.
+    #[default]
+    Relative,
+    /// The absolute path of the package entry.
+    Absolute,
+}
+
+/// How package files are installed into the target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum InstallMode {
+    /// Link package entries, folding directories where possible.
+    #[default]
+    Symlink,
+    /// Copy package files. Directories are created in the target, and
+    /// copies that were modified since they were installed are kept.
+    Copy,
+    /// Hard link package files. The package and the target must be on the
+    /// same file system.
+    Hardlink,
+}
+
+/// Returns the target for a symlink at `link_path` that points to `source`.
+pub fn link_target_for(source: &Path, link_path: &Path, mode: LinkMode) -> Result<PathBuf, Error> {
+    match mode {
+        LinkMode::Relative => {
+            let link_dir = link_path
+                .parent()
+                .ok_or_else(|| Error::PathNotAbsolute(link_path.to_path_buf()))?;
+            relative_path(TargetPath(source), BasePath(link_dir))
+        }
+        LinkMode::Absolute if source.is_absolute() => Ok(source.to_path_buf()),
+        LinkMode::Absolute => Err(Error::PathNotAbsolute(source.to_path_buf())),
+    }
+}
+
 /// normalize - like canonicalize, but does not fail if the path does not exist
 pub fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
     let path = path.as_ref();
@@ -56,7 +228,7 @@ pub fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
     for component in path.components() {
         match component {
             Component::RootDir => normalized.push("/"),
-            Component::Normal(part) => if !part.is_empty() { normalized.push(part) },
+            Component::Normal(part) if !part.is_empty() => normalized.push(part),
             Component::ParentDir => { normalized.pop(); },
             Component::CurDir => { },
             _ => {}
@@ -65,6 +237,114 @@ pub fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
     normalized
 }
 
+/// Returns the absolute, normalized path that the symlink `link` points to.
+/// Relative link targets are resolved against the directory containing the link.
+pub fn resolve_link(link: &Path, target: &Path) -> PathBuf {
+    match link.parent() {
+        Some(parent) => normalize_path(parent.join(target)),
+        None => normalize_path(target),
+    }
+}
+
+/// Returns true if the symlink at `link`, which points to `target`, links to
+/// `path`. Absolute targets and relative ones spelled with `.` or redundant
+/// `..` components are recognized as well.
+pub fn links_to(link: &Path, target: &Path, path: &Path) -> bool {
+    resolve_link(link, target) == normalize_path(path)
+}
+
+/// Moves the file `from` to `to`, replacing `to` if it exists. Falls back to
+/// copying when the two are on different file systems.
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<(), io::Error> {
+    let from = from.as_ref();
+    let to = to.as_ref();
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            std::fs::copy(from, to)?;
+            std::fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Copies the file `from` to `to`, which must not exist, with its permissions.
+pub fn copy_file<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<(), io::Error> {
+    let mut source = std::fs::File::open(from)?;
+    let mut copy = std::fs::File::create_new(to)?;
+    io::copy(&mut source, &mut copy)?;
+    copy.set_permissions(source.metadata()?.permissions())
+}
+
+/// Writes `content` to the new file `path`, with the permissions of `source`.
+pub fn write_file(source: &Path, path: &Path, content: &str) -> Result<(), io::Error> {
+    let mut file = std::fs::File::create_new(path)?;
+    io::Write::write_all(&mut file, content.as_bytes())?;
+    file.set_permissions(std::fs::metadata(source)?.permissions())
+}
+
+/// Returns the hex encoded SHA-256 hash of `content`.
+pub fn hash_of(content: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(content).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Returns the hex encoded SHA-256 hash of the content of the file at `path`.
+pub fn content_hash(path: &Path) -> Result<String, Error> {
+    use sha2::{Digest, Sha256};
+
+    let mut file = std::fs::File::open(path).at(IoAction::Read, path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).at(IoAction::Read, path)?;
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Returns true if `a` and `b` are the same file, i.e. hard links to each
+/// other. Symlinks are not followed and missing files are never the same.
+pub fn same_file(a: &Path, b: &Path) -> Result<bool, Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let (Some(a), Some(b)) = (metadata_of(a)?, metadata_of(b)?) else {
+            return Ok(false);
+        };
+        Ok(a.dev() == b.dev() && a.ino() == b.ino())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        Ok(false)
+    }
+}
+
+/// Returns true if `a` and `b` are on the same file system, so that hard
+/// links between them are possible.
+pub fn same_file_system(a: &Path, b: &Path) -> Result<bool, Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let a = std::fs::metadata(a).at(IoAction::Metadata, a)?;
+        let b = std::fs::metadata(b).at(IoAction::Metadata, b)?;
+        Ok(a.dev() == b.dev())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        Ok(true)
+    }
+}
+
+#[cfg(unix)]
+fn metadata_of(path: &Path) -> Result<Option<std::fs::Metadata>, Error> {
+    match symlink_metadata(path) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::io(IoAction::Metadata, path, e)),
+    }
+}
+
 /// Creates a symbolic link from `src` to `dst`.
 /// Automatically detects whether the source is a file or directory on Windows.
 pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), io::Error> {
@@ -88,67 +368,355 @@ pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), io:
     }
 }
 
+/// What a package entry is. Symlinks are not followed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    /// A symlink, with the target it points to.
+    Symlink(PathBuf),
+    /// Anything else, such as a socket or a device.
+    Special,
+}
+
+/// An entry of a package.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// The path of the entry, relative to the package root.
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Dir
+    }
+}
+
+/// A depth-first walk of the entries of a package, in order of their paths.
+///
+/// Each directory is read when the walk descends into it, which happens on
+/// the call to `next` after the directory itself was returned, unless
+/// `skip_dir` was called in between.
+pub struct Walk<'a, P: ?Sized> {
+    package: &'a P,
+    /// The entries left to return of each directory being walked, innermost last.
+    stack: Vec<std::vec::IntoIter<Entry>>,
+    /// The directory to descend into next.
+    pending: Option<PathBuf>,
+    /// Whether alternates that are not selected are returned as well.
+    all_alternates: bool,
+}
+
+impl<'a, P: Package + ?Sized> Walk<'a, P> {
+    fn new(package: &'a P, dir: &Path, all_alternates: bool) -> Self {
+        Self {
+            package,
+            stack: Vec::new(),
+            pending: Some(dir.to_path_buf()),
+            all_alternates,
+        }
+    }
+
+    /// Does not descend into the directory returned last.
+    pub fn skip_dir(&mut self) {
+        self.pending = None;
+    }
+}
+
+impl<P: Package + ?Sized> Iterator for Walk<'_, P> {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(dir) = self.pending.take() {
+            let entries = if self.all_alternates {
+                self.package.read_dir_with_alternates(&dir)
+            } else {
+                self.package.read_dir(&dir)
+            };
+            match entries {
+                Ok(entries) => self.stack.push(entries.into_iter()),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        while let Some(entries) = self.stack.last_mut() {
+            match entries.next() {
+                Some(entry) => {
+                    if entry.is_dir() {
+                        self.pending = Some(entry.path.clone());
+                    }
+                    return Some(Ok(entry));
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+        None
+    }
+}
+
 pub trait Package {
-    fn get_package_contents(&self) -> Result<Vec<PathBuf>, Error>;
+    /// Returns the entries of `dir`, which is relative to the package root,
+    /// ordered by path. The paths of the entries are relative to the package
+    /// root as well. Ignored entries are left out.
+    fn read_dir(&self, dir: &Path) -> Result<Vec<Entry>, Error>;
+
+    /// Walks all entries of the package that are not ignored. Nothing below
+    /// an ignored directory is visited.
+    fn walk(&self) -> Walk<'_, Self> {
+        Walk::new(self, Path::new(""), false)
+    }
+
+    /// Returns the entries of `dir` like `read_dir`, including the
+    /// alternates that are not selected.
+    fn read_dir_with_alternates(&self, dir: &Path) -> Result<Vec<Entry>, Error> {
+        self.read_dir(dir)
+    }
+
+    /// Walks all entries of the package like `walk`, including the
+    /// alternates that are not selected.
+    fn walk_with_alternates(&self) -> Walk<'_, Self> {
+        Walk::new(self, Path::new(""), true)
+    }
+
+    /// Returns true if `item`, a path relative to the package root, is left
+    /// out of the package contents.
+    fn is_ignored(&self, item: &Path) -> bool {
+        let _ = item;
+        false
+    }
+
+    /// Returns true if the file `item`, a path relative to the package root,
+    /// is a template that is rendered instead of linked.
+    fn is_template(&self, item: &Path) -> bool {
+        has_template_suffix(&base_path(item))
+    }
+
+    /// Returns true if the symlink at `link_path`, which points to
+    /// `link_target`, links to `item`, or to another alternate of it.
+    fn owns_link(&self, item: &Path, link_path: &Path, link_target: &Path) -> bool {
+        let resolved = resolve_link(link_path, link_target);
+        resolved == normalize_path(self.path().join(item))
+            || resolved
+                .strip_prefix(self.path())
+                .is_ok_and(|linked| base_path(linked) == base_path(item))
+    }
+
+    /// Returns the path, relative to the target, that `entry` is installed
+    /// at. Alternates are installed without their conditions, and templates
+    /// without their `.tmpl` suffix.
+    fn installed_path(&self, entry: &Entry) -> PathBuf {
+        let item = base_path(&entry.path);
+        if entry.kind == EntryKind::File && self.is_template(&entry.path) {
+            rendered_path(&item)
+        } else {
+            item
+        }
+    }
+
     fn path(&self) -> &Path;
+    fn name(&self) -> &str;
 }
 
 pub struct PackageImpl {
     path: PathBuf,
+    name: String,
+    ignore: IgnoreList,
+    templates: IgnoreList,
+    machine: Machine,
+    manifest: Manifest,
+    /// The entries selected in each directory `is_ignored` has looked at.
+    selected: RefCell<HashMap<PathBuf, Vec<PathBuf>>>,
 }
 
 impl Package for PackageImpl {
-    fn get_package_contents(&self) -> Result<Vec<PathBuf>, Error> {
+    fn read_dir(&self, dir: &Path) -> Result<Vec<Entry>, Error> {
+        let mut entries = self.read_dir_with_alternates(dir)?;
+        let mut selected = alternate::selected(entries.iter().map(|entry| entry.path.as_path()), &self.machine).into_iter();
+        entries.retain(|_| selected.next().unwrap_or(false));
+        Ok(entries)
+    }
+
+    fn read_dir_with_alternates(&self, dir: &Path) -> Result<Vec<Entry>, Error> {
         let package_dir = &self.path;
         if !package_dir.is_absolute() {
-            return Err(Error::PathNotAbsolute);
+            return Err(Error::PathNotAbsolute(package_dir.clone()));
         }
 
-        let mut contents = Vec::new();
-        let mut iter = package_dir.read_dir()?;
-        while let Some(entry) = iter.next() {
-            contents.push(PathBuf::from(entry?.file_name()));
+        let path = package_dir.join(dir);
+        let mut entries = Vec::new();
+        for entry in path.read_dir().at(IoAction::ReadDir, &path).map_err(|e| e.in_package(&self.name))? {
+            let entry = entry.at(IoAction::ReadDir, &path).map_err(|e| e.in_package(&self.name))?;
+            let item = dir.join(entry.file_name());
+            if self.ignore.matches(&item) {
+                continue;
+            }
+
+            let file_type = entry
+                .file_type()
+                .at(IoAction::Metadata, &entry.path())
+                .map_err(|e| e.in_package(&self.name))?;
+            let kind = if file_type.is_symlink() {
+                let link = read_link(entry.path())
+                    .at(IoAction::ReadLink, &entry.path())
+                    .map_err(|e| e.in_package(&self.name))?;
+                EntryKind::Symlink(link)
+            } else if file_type.is_dir() {
+                EntryKind::Dir
+            } else if file_type.is_file() {
+                EntryKind::File
+            } else {
+                EntryKind::Special
+            };
+            entries.push(Entry { path: item, kind });
         }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(entries)
+    }
+
+    fn is_ignored(&self, item: &Path) -> bool {
+        if self.ignore.matches(item) {
+            return true;
+        }
+        // Alternates that are not selected are left out as well.
+        let parent = item.parent().unwrap_or(Path::new(""));
+        let mut selected = self.selected.borrow_mut();
+        if !selected.contains_key(parent) {
+            let Ok(entries) = self.read_dir(parent) else {
+                return false;
+            };
+            let paths = entries.into_iter().map(|entry| entry.path).collect();
+            selected.insert(parent.to_path_buf(), paths);
+        }
+        !selected[parent].iter().any(|path| path == item)
+    }
 
-        Ok(contents)
+    fn is_template(&self, item: &Path) -> bool {
+        let item = base_path(item);
+        has_template_suffix(&item) || self.templates.matches(&item)
     }
 
     fn path(&self) -> &Path {
         &self.path
     }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 impl PackageImpl {
     pub fn new(package_dir: &Path, name: &str) -> Result<Self, Error> {
         if !package_dir.is_absolute() {
-            return Err(Error::PathNotAbsolute);
+            return Err(Error::PathNotAbsolute(package_dir.to_path_buf()));
         }
 
         let package_path = package_dir.join(name);
         if !package_path.exists() {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!(
-                    "Package '{}' not found in '{}'",
-                    name,
-                    package_dir.display()
-                ),
-            )));
+            return Err(Error::PackageNotFound {
+                name: name.to_string(),
+                dir: package_dir.to_path_buf(),
+            });
         }
 
-        Ok(Self { path: package_path.canonicalize()? })
+        let path = package_path
+            .canonicalize()
+            .at(IoAction::Canonicalize, &package_path)
+            .map_err(|e| e.in_package(name))?;
+        let ignore = IgnoreList::for_package(&path).map_err(|e| e.in_package(name))?;
+        let templates = IgnoreList::read(&path.join(PACKAGE_TEMPLATES_FILE))
+            .map_err(|e| e.in_package(name))?
+            .unwrap_or_default();
+        let manifest = Manifest::read(&path).map_err(|e| e.in_package(name))?;
+        Ok(Self {
+            path,
+            name: name.to_string(),
+            ignore,
+            templates,
+            machine: Machine::default(),
+            manifest,
+            selected: RefCell::default(),
+        })
+    }
+
+    /// Ignores the entries matching `patterns` in addition to the ones
+    /// ignored by the package's own ignore list.
+    pub fn ignoring(mut self, patterns: &IgnoreList) -> Self {
+        self.ignore.extend(patterns);
+        self.selected.get_mut().clear();
+        self
+    }
+
+    /// Selects the alternates for `machine`. Without a machine, only the
+    /// alternates for which `default` is the only condition are selected.
+    pub fn selecting(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self.selected.get_mut().clear();
+        self
+    }
+
+    /// Returns the packages that this package depends and conflicts with, as
+    /// declared in its `syra.toml`.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Returns the package in `stow_dir` that contains `path`, along with
+    /// `path` relative to that package.
+    pub fn containing(
+        stow_dir: &Path,
+        path: &Path,
+        patterns: &IgnoreList,
+        machine: &Machine,
+    ) -> Option<(Self, PathBuf)> {
+        let relative = path.strip_prefix(stow_dir).ok()?;
+        let mut components = relative.components();
+        let name = components.next()?.as_os_str().to_str()?;
+        let package = Self::new(stow_dir, name).ok()?.ignoring(patterns).selecting(machine.clone());
+        Some((package, components.as_path().to_path_buf()))
     }
 }
 
+/// What the target contains at the path of a package entry.
 pub enum InstalledItem {
+    /// A link to the package entry.
     Item(Symlink),
-    NotOwned(PathBuf), // This is either a directory, or a symlink pointing to outside the package.
+    /// A link to something other than the package entry.
+    NotOwned(Symlink),
+    /// A real directory. The package entries inside it are reported as well.
+    Dir(PathBuf),
+    /// A copy of the package entry, a hard link to it or the file rendered
+    /// from it, installed by syra.
+    Installed(PathBuf),
+    /// A copy or rendered file that was modified after it was installed.
+    Modified(PathBuf),
+    /// A file, or anything else that is neither a directory nor a link.
+    File(PathBuf),
+    /// Nothing, the package entry is not installed.
+    Missing(PathBuf),
+}
+
+impl InstalledItem {
+    /// Returns true if the package entry is installed by syra, as a link or
+    /// as an unmodified copy or hard link.
+    pub fn is_owned(&self) -> bool {
+        matches!(self, InstalledItem::Item(_) | InstalledItem::Installed(_))
+    }
 }
 
 pub trait Target {
     fn path(&self) -> &Path;
-    // Retusn to links in target directory that point to files/directories in the package.
-    fn get_installed_package_contents<PackageT: Package>(&self, package: &PackageT) -> Result<Vec<InstalledItem>, Error>;
+    /// Returns what the target contains at the path of each entry of the
+    /// package, descending into the real directories of the target. `state`
+    /// tells the files that syra copied apart from other files.
+    fn get_installed_package_contents<PackageT: Package>(
+        &self,
+        package: &PackageT,
+        state: &State,
+    ) -> Result<Vec<InstalledItem>, Error>;
 
     fn relative_path_to_package<P: Package>(&self, package: &P) -> Result<PathBuf, Error> {
         relative_path(TargetPath(package.path()), BasePath(self.path()))
@@ -157,6 +725,7 @@ pub trait Target {
 
 pub struct TargetImpl {
     path: PathBuf,
+    dotfiles: bool,
 }
 
 impl Target for TargetImpl {
@@ -164,22 +733,67 @@ impl Target for TargetImpl {
         &self.path
     }
 
-    fn get_installed_package_contents<PackageT: Package>(&self, package: &PackageT) -> Result<Vec<InstalledItem>, Error> {
-        let _ = package;
-        todo!("Implement get_installed_package_contents for TargetImpl");
+    fn get_installed_package_contents<PackageT: Package>(
+        &self,
+        package: &PackageT,
+        state: &State,
+    ) -> Result<Vec<InstalledItem>, Error> {
+        let mut items = Vec::new();
+        let mut walk = package.walk();
+        while let Some(entry) = walk.next() {
+            let item = self.scan_entry(package, state, &entry?).map_err(|e| e.in_package(package.name()))?;
+            let descend = matches!(item, InstalledItem::Dir(_));
+            items.push(item);
+            if !descend {
+                walk.skip_dir();
+            }
+        }
+        Ok(items)
     }
 }
 
 impl TargetImpl {
     pub fn new(path: &Path) -> Result<Self, Error> {
         if !path.is_absolute() {
-            Err(Error::PathNotAbsolute)
+            Err(Error::PathNotAbsolute(path.to_path_buf()))
         }
         else {
-            let path = path.canonicalize()?;
-            Ok(Self { path })
+            let path = path.canonicalize().at(IoAction::Canonicalize, path)?;
+            Ok(Self { path, dotfiles: false })
         }
     }
+
+    /// Expects package entries named `dot-*` to be linked as `.*`.
+    pub fn with_dotfiles(mut self, dotfiles: bool) -> Self {
+        self.dotfiles = dotfiles;
+        self
+    }
+
+    /// Returns what the target contains at the path of `entry`.
+    fn scan_entry<P: Package>(&self, package: &P, state: &State, entry: &Entry) -> Result<InstalledItem, Error> {
+        let item = package.installed_path(entry);
+        let path = if self.dotfiles {
+            self.path.join(dotfiles_path(&item))
+        } else {
+            self.path.join(&item)
+        };
+        let source = package.path().join(&entry.path);
+
+        Ok(match PathState::of(&path)? {
+            PathState::Symlink(target) if package.owns_link(&entry.path, &path, &target) => {
+                InstalledItem::Item(Symlink { path, target })
+            }
+            PathState::Symlink(target) => InstalledItem::NotOwned(Symlink { path, target }),
+            PathState::Dir if entry.is_dir() => InstalledItem::Dir(path),
+            PathState::File if entry.kind == EntryKind::File => match state.origin_of(&path, &source)? {
+                FileOrigin::Hardlink | FileOrigin::Copy => InstalledItem::Installed(path),
+                FileOrigin::ModifiedCopy => InstalledItem::Modified(path),
+                FileOrigin::Foreign => InstalledItem::File(path),
+            },
+            PathState::Dir | PathState::File => InstalledItem::File(path),
+            PathState::Missing => InstalledItem::Missing(path),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -192,7 +806,7 @@ mod tests {
         let target = Path::new("/home/user/project/src");
         let base = Path::new("/home/user/docs");
         assert_eq!(
-            relative_path(TargetPath(&target), BasePath(&base)).unwrap(),
+            relative_path(TargetPath(target), BasePath(base)).unwrap(),
             PathBuf::from("../project/src")
         );
     }
@@ -202,7 +816,7 @@ mod tests {
         let target = Path::new("/a/b/c");
         let base = Path::new("/x/y/z");
         assert_eq!(
-            relative_path(TargetPath(&target), BasePath(&base)).unwrap(),
+            relative_path(TargetPath(target), BasePath(base)).unwrap(),
             PathBuf::from("../../../a/b/c")
         );
     }
@@ -212,7 +826,7 @@ mod tests {
         let target = Path::new("/same/path");
         let base = Path::new("/same/path");
         assert_eq!(
-            relative_path(TargetPath(&target), BasePath(&base)).unwrap(),
+            relative_path(TargetPath(target), BasePath(base)).unwrap(),
             PathBuf::from("")
         );
     }
@@ -222,7 +836,7 @@ mod tests {
         let target = Path::new("/a/b/c/d");
         let base = Path::new("/a/b");
         assert_eq!(
-            relative_path(TargetPath(&target), BasePath(&base)).unwrap(),
+            relative_path(TargetPath(target), BasePath(base)).unwrap(),
             PathBuf::from("c/d")
         );
     }
@@ -232,7 +846,7 @@ mod tests {
         let target = Path::new("/a/b");
         let base = Path::new("/a/b/c/d");
         assert_eq!(
-            relative_path(TargetPath(&target), BasePath(&base)).unwrap(),
+            relative_path(TargetPath(target), BasePath(base)).unwrap(),
             PathBuf::from("../../")
         );
     }
@@ -241,9 +855,9 @@ mod tests {
     fn test_error_on_relative_target() {
         let target = Path::new("a/b/c");
         let base = Path::new("/a/b");
-        match relative_path(TargetPath(&target), BasePath(&base)) {
-            Err(Error::PathNotAbsolute) => (),
-            _ => assert!(false, "Expected PathNotAbsolute error"),
+        match relative_path(TargetPath(target), BasePath(base)) {
+            Err(Error::PathNotAbsolute(path)) if path == target => (),
+            _ => panic!("Expected PathNotAbsolute error"),
         }
     }
 
@@ -251,12 +865,20 @@ mod tests {
     fn test_error_on_relative_base() {
         let target = Path::new("/a/b/c");
         let base = Path::new("a/b");
-        match relative_path(TargetPath(&target), BasePath(&base)) {
-            Err(Error::PathNotAbsolute) => (),
-            _ => assert!(false, "Expected PathNotAbsolute error"),
+        match relative_path(TargetPath(target), BasePath(base)) {
+            Err(Error::PathNotAbsolute(path)) if path == base => (),
+            _ => panic!("Expected PathNotAbsolute error"),
         }
     }
 
+    #[test]
+    fn test_dotfiles_path() {
+        assert_eq!(dotfiles_path(Path::new("dot-bashrc")), PathBuf::from(".bashrc"));
+        assert_eq!(dotfiles_path(Path::new("dot-config/app/dot-rc")), PathBuf::from(".config/app/.rc"));
+        assert_eq!(dotfiles_path(Path::new("dot-")), PathBuf::from("dot-"));
+        assert_eq!(dotfiles_path(Path::new("my-dot-file")), PathBuf::from("my-dot-file"));
+    }
+
     #[test]
     fn test_normalize_path1() {
         let path = Path::new("/");
@@ -293,4 +915,119 @@ mod tests {
         let normalized = normalize_path(path);
         assert_eq!(normalized, PathBuf::from("/qwe"));
     }
+
+    #[test]
+    fn test_walk() {
+        let tmp = tempfile::tempdir().unwrap();
+        let stow_dir = tmp.path().canonicalize().unwrap();
+        for dir in ["a/.config/app", "a/.config/other", "a/.git"] {
+            std::fs::create_dir_all(stow_dir.join(dir)).unwrap();
+        }
+        for file in ["a/.bashrc", "a/.config/app/rc", "a/.config/other/rc", "a/.git/HEAD"] {
+            std::fs::write(stow_dir.join(file), "").unwrap();
+        }
+        symlink(".bashrc", stow_dir.join("a/.profile")).unwrap();
+
+        let package = PackageImpl::new(&stow_dir, "a").unwrap();
+        let entries: Vec<Entry> = package.walk().collect::<Result<_, _>>().unwrap();
+        let entry = |path: &str, kind: EntryKind| Entry {
+            path: PathBuf::from(path),
+            kind,
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry(".bashrc", EntryKind::File),
+                entry(".config", EntryKind::Dir),
+                entry(".config/app", EntryKind::Dir),
+                entry(".config/app/rc", EntryKind::File),
+                entry(".config/other", EntryKind::Dir),
+                entry(".config/other/rc", EntryKind::File),
+                entry(".profile", EntryKind::Symlink(PathBuf::from(".bashrc"))),
+            ]
+        );
+
+        let mut walk = package.walk();
+        let mut paths = Vec::new();
+        while let Some(entry) = walk.next() {
+            let entry = entry.unwrap();
+            if entry.path == Path::new(".config/app") {
+                walk.skip_dir();
+            }
+            paths.push(entry.path);
+        }
+        assert_eq!(
+            paths,
+            [".bashrc", ".config", ".config/app", ".config/other", ".config/other/rc", ".profile"]
+                .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_walk_skips_ignored_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let stow_dir = tmp.path().canonicalize().unwrap();
+        std::fs::create_dir_all(stow_dir.join("a/.config/app")).unwrap();
+        std::fs::write(stow_dir.join("a/.config/app/rc"), "").unwrap();
+
+        let ignore = IgnoreList::new(&["^/.config/app$".to_string()]).unwrap();
+        let package = PackageImpl::new(&stow_dir, "a").unwrap().ignoring(&ignore);
+        let paths: Vec<PathBuf> = package.walk().map(|entry| entry.unwrap().path).collect();
+        assert_eq!(paths, vec![PathBuf::from(".config")]);
+    }
+
+    #[test]
+    fn test_package_not_found() {
+        let tmp = tempfile::tempdir().unwrap();
+        let stow_dir = tmp.path().canonicalize().unwrap();
+        match PackageImpl::new(&stow_dir, "a") {
+            Err(Error::PackageNotFound { name, dir }) if name == "a" && dir == stow_dir => (),
+            _ => panic!("Expected PackageNotFound error"),
+        }
+    }
+
+    #[test]
+    fn test_get_installed_package_contents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        for dir in ["stow/a/.config/app", "target/.config"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["stow/a/.bashrc", "stow/a/.inputrc", "stow/a/.profile", "stow/a/.vimrc", "stow/a/.config/app/rc", "target/.profile"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        std::fs::hard_link(root.join("stow/a/.inputrc"), root.join("target/.inputrc")).unwrap();
+        symlink("../stow/a/.bashrc", root.join("target/.bashrc")).unwrap();
+        symlink("/etc/vimrc", root.join("target/.vimrc")).unwrap();
+
+        let package = PackageImpl::new(&root.join("stow"), "a").unwrap();
+        let target = TargetImpl::new(&root.join("target")).unwrap();
+        let state = State::load(target.path()).unwrap();
+        let items = target.get_installed_package_contents(&package, &state).unwrap();
+
+        let described: Vec<String> = items
+            .iter()
+            .map(|item| match item {
+                InstalledItem::Item(link) => format!("owned {}", link.path.display()),
+                InstalledItem::NotOwned(link) => format!("foreign {}", link.path.display()),
+                InstalledItem::Installed(path) => format!("installed {}", path.display()),
+                InstalledItem::Modified(path) => format!("modified {}", path.display()),
+                InstalledItem::Dir(path) => format!("dir {}", path.display()),
+                InstalledItem::File(path) => format!("file {}", path.display()),
+                InstalledItem::Missing(path) => format!("missing {}", path.display()),
+            })
+            .collect();
+        let target_dir = root.join("target");
+        assert_eq!(
+            described,
+            vec![
+                format!("owned {}", target_dir.join(".bashrc").display()),
+                format!("dir {}", target_dir.join(".config").display()),
+                format!("missing {}", target_dir.join(".config/app").display()),
+                format!("installed {}", target_dir.join(".inputrc").display()),
+                format!("file {}", target_dir.join(".profile").display()),
+                format!("foreign {}", target_dir.join(".vimrc").display()),
+            ]
+        );
+    }
 }
@@ -0,0 +1,118 @@
+//! Hooks, commands that packages run around the changes syra makes for them.
+//!
+//! A package declares its hooks in the `[hooks]` table of its `syra.toml`:
+//! - `pre-stow` and `post-stow`, run when the package is stowed or restowed
+//! - `pre-unstow` and `post-unstow`, run when it is unstowed
+//!
+//! Hooks only run for packages that have changes to make, and never with
+//! `-n`. A hook is run with `sh -c` in the package directory, with these
+//! environment variables:
+//! - `SYRA_HOOK`, e.g. `post-stow`
+//! - `SYRA_PACKAGE`, the name of the package
+//! - `SYRA_PACKAGE_DIR`, the path of the package
+//! - `SYRA_TARGET_DIR`, the path of the target directory
+//! - `SYRA_CHANGED_PATHS`, the paths in the target that are changed for the
+//!   package, one per line
+//!
+//! A hook fails if it exits with a non-zero status. If a pre hook fails, the
+//! package is left alone; if a post hook fails, its changes are rolled back.
+//! Either way the run stops there, and the packages before it stay changed.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::error::Error;
+use crate::fs::{Package, PackageImpl};
+use crate::log::Log;
+use crate::manifest::Hooks;
+use crate::plan::Operation;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hook {
+    PreStow,
+    PostStow,
+    PreUnstow,
+    PostUnstow,
+}
+
+impl Hook {
+    /// Returns the hooks run before and after `operation`. Restowing leaves
+    /// the package stowed, so it runs the stow hooks.
+    pub fn around(operation: Operation) -> (Hook, Hook) {
+        match operation {
+            Operation::Stow | Operation::Restow => (Hook::PreStow, Hook::PostStow),
+            Operation::Unstow => (Hook::PreUnstow, Hook::PostUnstow),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreStow => "pre-stow",
+            Hook::PostStow => "post-stow",
+            Hook::PreUnstow => "pre-unstow",
+            Hook::PostUnstow => "post-unstow",
+        }
+    }
+
+    fn command<'a>(&self, hooks: &'a Hooks) -> Option<&'a str> {
+        match self {
+            Hook::PreStow => hooks.pre_stow.as_deref(),
+            Hook::PostStow => hooks.post_stow.as_deref(),
+            Hook::PreUnstow => hooks.pre_unstow.as_deref(),
+            Hook::PostUnstow => hooks.post_unstow.as_deref(),
+        }
+    }
+}
+
+/// Runs the hooks of packages in a target.
+pub struct Runner<'a> {
+    pub target_dir: &'a Path,
+    pub log: Log,
+    /// Sends the output of hooks to stderr, keeping stdout for the report.
+    pub json: bool,
+}
+
+impl Runner<'_> {
+    /// Runs `hook` of `package`, if it declares one. `changed` are the paths
+    /// in the target that are changed for the package.
+    pub fn run(&self, hook: Hook, package: &PackageImpl, changed: &[&Path]) -> Result<(), Error> {
+        let Some(command) = hook.command(&package.manifest().hooks) else {
+            return Ok(());
+        };
+        self.log.write(format_args!(
+            "hook: running {} of package {}: {}",
+            hook.name(),
+            package.name(),
+            command
+        ));
+
+        let changed: BTreeSet<&Path> = changed.iter().copied().collect();
+        let changed: Vec<String> = changed.iter().map(|path| path.display().to_string()).collect();
+        let mut process = Command::new("sh");
+        process
+            .arg("-c")
+            .arg(command)
+            .current_dir(package.path())
+            .env("SYRA_HOOK", hook.name())
+            .env("SYRA_PACKAGE", package.name())
+            .env("SYRA_PACKAGE_DIR", package.path())
+            .env("SYRA_TARGET_DIR", self.target_dir)
+            .env("SYRA_CHANGED_PATHS", changed.join("\n"));
+        if self.json {
+            process.stdout(Stdio::from(io::stderr()));
+        }
+
+        let failed = |reason: String| Error::Hook {
+            package: package.name().to_string(),
+            hook: hook.name(),
+            reason,
+        };
+        let status = process.status().map_err(|e| failed(e.to_string()))?;
+        if !status.success() {
+            return Err(failed(status.to_string()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Ignore lists, deciding which entries of a package are never linked.
+//!
+//! Each line of an ignore file is a pattern; blank lines and lines starting
+//! with `#` are skipped. Patterns are regular expressions unless prefixed with
+//! `glob:`. A pattern has to match either the name of an entry or its path
+//! relative to the package root, prefixed with `/`, in full. So `\.git`
+//! ignores every `.git` in the package, while `^/README.*` only ignores
+//! READMEs at the top of the package.
+//!
+//! The patterns for a package are the built-in defaults, together with the
+//! ones in the `.syra-global-ignore` file in the home directory and in the
+//! `.syra-ignore` file in the package, if they exist.
+//!
+//! Patterns given with `--ignore` are always added, and the `.syra-ignore`,
+//! `.syra-templates` and `syra.toml` files are always ignored.
+
+use std::env;
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::error::{Error, IoAction};
+use crate::manifest;
+use crate::template::PACKAGE_TEMPLATES_FILE;
+
+pub const PACKAGE_IGNORE_FILE: &str = ".syra-ignore";
+pub const GLOBAL_IGNORE_FILE: &str = ".syra-global-ignore";
+
+const DEFAULT_PATTERNS: &[&str] = &[
+    r"RCS",
+    r".+,v",
+    r"CVS",
+    r"\.\#.+",
+    r"\.cvsignore",
+    r"\.svn",
+    r"_darcs",
+    r"\.hg",
+    r"\.git",
+    r"\.gitignore",
+    r"\.gitmodules",
+    r".+~",
+    r"\#.*\#",
+    r"\..+\.sw[a-p]",
+    r"^/README.*",
+    r"^/LICENSE.*",
+    r"^/COPYING",
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreList {
+    patterns: Vec<Regex>,
+}
+
+impl IgnoreList {
+    /// Compiles `patterns`, each of which is a regular expression or a glob
+    /// prefixed with `glob:`.
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self, Error> {
+        let mut list = Self::default();
+        for pattern in patterns {
+            list.add(pattern.as_ref())?;
+        }
+        Ok(list)
+    }
+
+    /// Returns the ignore list for the package at `package_path`, without any
+    /// patterns given on the command line.
+    pub fn for_package(package_path: &Path) -> Result<Self, Error> {
+        let mut list = Self::new(DEFAULT_PATTERNS)?;
+        if let Some(global) = global_ignore_file().map(|path| Self::read(&path)).transpose()?.flatten() {
+            list.extend(&global);
+        }
+        if let Some(package) = Self::read(&package_path.join(PACKAGE_IGNORE_FILE))? {
+            list.extend(&package);
+        }
+        list.add(&format!("^/{}", regex::escape(PACKAGE_IGNORE_FILE)))?;
+        list.add(&format!("^/{}", regex::escape(PACKAGE_TEMPLATES_FILE)))?;
+        list.add(&format!("^/{}", regex::escape(manifest::FILE_NAME)))?;
+        Ok(list)
+    }
+
+    /// Reads the patterns in the file at `path`, or returns `None` if there is no such file.
+    pub fn read(path: &Path) -> Result<Option<Self>, Error> {
+        let contents = match read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::io(IoAction::Read, path, e)),
+        };
+
+        let patterns: Vec<&str> = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        Self::new(&patterns).map(Some)
+    }
+
+    /// Adds `pattern`, a regular expression or a glob prefixed with `glob:`.
+    pub fn add(&mut self, pattern: &str) -> Result<(), Error> {
+        let expression = match pattern.strip_prefix("glob:") {
+            Some(glob) => glob_to_regex(glob),
+            None => pattern.to_string(),
+        };
+        let regex = Regex::new(&format!("^(?:{})$", expression))
+            .map_err(|e| Error::InvalidIgnorePattern(pattern.to_string(), e.to_string()))?;
+        self.patterns.push(regex);
+        Ok(())
+    }
+
+    /// Adds the patterns of `other` to this list.
+    pub fn extend(&mut self, other: &IgnoreList) {
+        self.patterns.extend(other.patterns.iter().cloned());
+    }
+
+    /// Returns true if one of the patterns matches `item`, a path relative to
+    /// the package root.
+    pub fn matches(&self, item: &Path) -> bool {
+        let name = item.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let path = format!("/{}", item.to_string_lossy());
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.is_match(&name) || pattern.is_match(&path))
+    }
+}
+
+fn global_ignore_file() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(GLOBAL_IGNORE_FILE))
+}
+
+/// Translates a glob into a regular expression. `*` and `?` do not match
+/// `/`, while `**` matches any number of path components.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+
+    #[test]
+    fn test_default_patterns() {
+        let list = IgnoreList::new(DEFAULT_PATTERNS).unwrap();
+        assert!(list.matches(Path::new(".git")));
+        assert!(list.matches(Path::new(".config/nvim/.git")));
+        assert!(list.matches(Path::new(".bashrc~")));
+        assert!(list.matches(Path::new(".config/.init.vim.swp")));
+        assert!(list.matches(Path::new("README.md")));
+        assert!(!list.matches(Path::new(".config/README.md")));
+        assert!(!list.matches(Path::new(".gitconfig")));
+        assert!(!list.matches(Path::new(".bashrc")));
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let list = IgnoreList::new(&["glob:*.bak", "glob:/.config/**/cache"]).unwrap();
+        assert!(list.matches(Path::new(".bashrc.bak")));
+        assert!(list.matches(Path::new(".config/app/.bashrc.bak")));
+        assert!(list.matches(Path::new(".config/app/cache")));
+        assert!(!list.matches(Path::new(".local/cache")));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(matches!(
+            IgnoreList::new(&["("]),
+            Err(Error::InvalidIgnorePattern(pattern, _)) if pattern == "("
+        ));
+    }
+
+    #[test]
+    fn test_package_ignore_file_adds_to_defaults() {
+        let tmp = tempfile::tempdir().unwrap();
+        create_dir_all(tmp.path()).unwrap();
+        write(tmp.path().join(PACKAGE_IGNORE_FILE), "# comment\n\n\\.local\n").unwrap();
+
+        let list = IgnoreList::for_package(tmp.path()).unwrap();
+        assert!(list.matches(Path::new(".local")));
+        assert!(list.matches(Path::new(PACKAGE_IGNORE_FILE)));
+        assert!(list.matches(Path::new("README.md")));
+        assert!(list.matches(Path::new(".git")));
+        assert!(!list.matches(Path::new(".bashrc")));
+    }
+}
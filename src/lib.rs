@@ -0,0 +1,42 @@
+//! syra links the contents of package directories into a target directory,
+//! like GNU Stow.
+//!
+//! `Syra` stows, unstows and restows packages the way the `syra` command
+//! does, and returns what it did as a report. The pieces it is made of can
+//! be used on their own: a `Planner` computes the actions that apply an
+//! operation to `Package`s in a `Target`, and an `Executor` runs them,
+//! rolling them back if one fails.
+//!
+//! The command line is behind the `cli` feature, which is on by default.
+
+pub mod alternate;
+pub mod doctor;
+pub mod error;
+pub mod exec;
+pub mod exit;
+pub mod fs;
+pub mod hook;
+pub mod ignore;
+pub mod log;
+pub mod manifest;
+pub mod plan;
+pub mod report;
+pub mod run;
+pub mod state;
+pub mod status;
+pub mod template;
+
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "cli")]
+pub mod commands;
+#[cfg(feature = "cli")]
+mod config;
+
+pub use error::Error;
+pub use exec::Executor;
+pub use fs::{Action, InstallMode, LinkMode, Package, PackageImpl, Target, TargetImpl};
+pub use plan::{Operation, Options, Plan, Planner, SkipReason, Skipped};
+pub use report::OperationReport;
+pub use run::{Completion, Failure, Syra};
+pub use state::State;
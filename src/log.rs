@@ -0,0 +1,61 @@
+//! Verbose messages about what syra does.
+//!
+//! The library never prints them itself: they are passed to a function the
+//! caller supplies, and dropped if there is none. The command line prints
+//! them on stdout with `-v`.
+
+use std::fmt;
+use std::sync::Arc;
+
+type Sink = dyn Fn(&str) + Send + Sync;
+
+/// Where verbose messages go.
+#[derive(Clone, Default)]
+pub struct Log {
+    sink: Option<Arc<Sink>>,
+}
+
+impl Log {
+    /// Returns a log that passes every message to `sink`.
+    pub fn new(sink: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self {
+            sink: Some(Arc::new(sink)),
+        }
+    }
+
+    /// Returns true if messages go anywhere.
+    pub fn is_enabled(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    /// Passes `message` to the sink. It is only formatted if there is one.
+    pub fn write(&self, message: fmt::Arguments<'_>) {
+        if let Some(sink) = &self.sink {
+            sink(&message.to_string());
+        }
+    }
+}
+
+impl fmt::Debug for Log {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Log").field("enabled", &self.is_enabled()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_log_passes_messages_to_sink() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let sink = messages.clone();
+        let log = Log::new(move |message| sink.lock().unwrap().push(message.to_string()));
+
+        log.write(format_args!("plan: {}", 1));
+        Log::default().write(format_args!("dropped"));
+
+        assert_eq!(*messages.lock().unwrap(), vec!["plan: 1"]);
+    }
+}
@@ -1,35 +1,90 @@
-mod cli;
-mod fs;
-mod error;
-mod commands;
+use std::process::ExitCode;
 
-use cli::{Cli, Commands};
 use clap::Parser;
+use syra::cli::{Cli, Commands, OutputFormat};
+use syra::commands::{doctor, list, restow, status, stow, unstow};
+use syra::exit;
 
-use commands::{stow, unstow};
-
-fn main() {
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    let output = cli.output;
+    let text = output == OutputFormat::Text;
 
-    match cli.command {
+    let code = match cli.command {
         Commands::Stow(args) => {
-            println!("stow::run");
-            match stow::run(args) {
-                Ok(_) => println!("Stow operation completed successfully."),
+            if text {
+                println!("stow::run");
+            }
+            match stow::run(args, output) {
+                Ok(completion) => {
+                    if text {
+                        println!("Stow operation completed successfully.");
+                    }
+                    completion.exit_code()
+                }
 
-                // TODO: handle error properly
-                Err(e) => eprintln!("Error during stow operation: {:?}", e),
+                Err(e) => {
+                    eprintln!("Error during stow operation: {}", e);
+                    e.exit_code()
+                }
             }
         },
         Commands::Unstow(args) => {
-            println!("unstow::run");
-            match unstow::run(args) {
-                Ok(_) => println!("Unstow operation completed successfully."),
-                Err(e) => eprintln!("Error during unstow operation: {:?}", e),
+            if text {
+                println!("unstow::run");
+            }
+            match unstow::run(args, output) {
+                Ok(completion) => {
+                    if text {
+                        println!("Unstow operation completed successfully.");
+                    }
+                    completion.exit_code()
+                }
+                Err(e) => {
+                    eprintln!("Error during unstow operation: {}", e);
+                    e.exit_code()
+                }
             }
         }
-        Commands::Restow(_) => {
-            todo!("Restow operation not implemented yet");
+        Commands::Restow(args) => {
+            if text {
+                println!("restow::run");
+            }
+            match restow::run(args, output) {
+                Ok(completion) => {
+                    if text {
+                        println!("Restow operation completed successfully.");
+                    }
+                    completion.exit_code()
+                }
+                Err(e) => {
+                    eprintln!("Error during restow operation: {}", e);
+                    e.exit_code()
+                }
+            }
         }
-    }
+        Commands::Status(args) => match status::run(args, output) {
+            Ok(()) => exit::SUCCESS,
+            Err(e) => {
+                eprintln!("Error during status operation: {}", e);
+                e.exit_code()
+            }
+        },
+        Commands::List(args) => match list::run(args, output) {
+            Ok(()) => exit::SUCCESS,
+            Err(e) => {
+                eprintln!("Error during list operation: {}", e);
+                e.exit_code()
+            }
+        },
+        Commands::Doctor(args) => match doctor::run(args, output) {
+            Ok(completion) => completion.exit_code(),
+            Err(e) => {
+                eprintln!("Error during doctor operation: {}", e);
+                e.exit_code()
+            }
+        },
+    };
+
+    ExitCode::from(code)
 }
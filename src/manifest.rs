@@ -0,0 +1,171 @@
+//! Package manifests, declaring how packages relate to each other.
+//!
+//! A package can contain a `syra.toml` file at its root, which is never
+//! linked:
+//!
+//! ```toml
+//! # Packages that are stowed along with this one, before it.
+//! depends = ["fonts", "shell-common"]
+//! # Packages that cannot be stowed at the same time as this one.
+//! conflicts = ["vim"]
+//!
+//! # Commands run before and after the package is changed, see `hook`.
+//! [hooks]
+//! post-stow = "fc-cache"
+//! ```
+//!
+//! Stowing a package stows its dependencies first, and theirs before them.
+//! Unstowing a package that a stowed package depends on is refused unless
+//! `--force` is given.
+
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::{Error, IoAction};
+
+pub const FILE_NAME: &str = "syra.toml";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub depends: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub hooks: Hooks,
+}
+
+/// The commands to run around changes to the package.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Hooks {
+    pub pre_stow: Option<String>,
+    pub post_stow: Option<String>,
+    pub pre_unstow: Option<String>,
+    pub post_unstow: Option<String>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the package at `package_path`. A package
+    /// without one has no dependencies or conflicts.
+    pub fn read(package_path: &Path) -> Result<Self, Error> {
+        let path = package_path.join(FILE_NAME);
+        let contents = match read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::io(IoAction::Read, &path, e)),
+        };
+        toml::from_str(&contents).map_err(|e| Error::InvalidConfig(path, e.message().to_string()))
+    }
+}
+
+/// Returns the packages named `names` together with all of their
+/// dependencies, each package after the ones it depends on. `load` returns
+/// the package with a name and its manifest.
+pub fn resolve<P, F>(names: &[String], mut load: F) -> Result<Vec<P>, Error>
+where
+    F: FnMut(&str) -> Result<(P, Manifest), Error>,
+{
+    let mut resolved = Vec::new();
+    let mut done = HashSet::new();
+    for name in names {
+        visit(name, &mut load, &mut Vec::new(), &mut done, &mut resolved)?;
+    }
+    Ok(resolved)
+}
+
+/// Adds `name` and its dependencies to `resolved`. `path` holds the packages
+/// whose dependencies are being visited, to detect cycles.
+fn visit<P, F>(
+    name: &str,
+    load: &mut F,
+    path: &mut Vec<String>,
+    done: &mut HashSet<String>,
+    resolved: &mut Vec<P>,
+) -> Result<(), Error>
+where
+    F: FnMut(&str) -> Result<(P, Manifest), Error>,
+{
+    if done.contains(name) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|visiting| visiting == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name.to_string());
+        return Err(Error::DependencyCycle(cycle));
+    }
+
+    let (package, manifest) = load(name)?;
+    path.push(name.to_string());
+    for dependency in &manifest.depends {
+        visit(dependency, load, path, done, resolved)?;
+    }
+    path.pop();
+
+    done.insert(name.to_string());
+    resolved.push(package);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve_in(graph: &[(&str, &[&str])], names: &[&str]) -> Result<Vec<String>, Error> {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        resolve(&names, |name| {
+            let (_, depends) = graph.iter().find(|(package, _)| *package == name).unwrap();
+            let manifest = Manifest {
+                depends: depends.iter().map(|name| name.to_string()).collect(),
+                ..Default::default()
+            };
+            Ok((name.to_string(), manifest))
+        })
+    }
+
+    #[test]
+    fn test_resolve_orders_dependencies_first() {
+        let graph: &[(&str, &[&str])] = &[
+            ("nvim", &["fonts", "shell-common"]),
+            ("fonts", &[]),
+            ("shell-common", &["fonts"]),
+            ("git", &["shell-common"]),
+        ];
+
+        assert_eq!(
+            resolve_in(graph, &["nvim", "git"]).unwrap(),
+            vec!["fonts", "shell-common", "nvim", "git"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_detects_cycles() {
+        let graph: &[(&str, &[&str])] = &[("a", &["b"]), ("b", &["c"]), ("c", &["b"])];
+
+        assert!(matches!(
+            resolve_in(graph, &["a"]),
+            Err(Error::DependencyCycle(cycle)) if cycle == ["b", "c", "b"]
+        ));
+    }
+
+    #[test]
+    fn test_read_manifest() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(Manifest::read(tmp.path()).unwrap(), Manifest::default());
+
+        std::fs::write(tmp.path().join(FILE_NAME), "depends = ['fonts']\nconflicts = ['vim']\n").unwrap();
+        let manifest = Manifest::read(tmp.path()).unwrap();
+        assert_eq!(manifest.depends, vec!["fonts"]);
+        assert_eq!(manifest.conflicts, vec!["vim"]);
+
+        std::fs::write(tmp.path().join(FILE_NAME), "[hooks]\npost-stow = 'fc-cache'\n").unwrap();
+        let manifest = Manifest::read(tmp.path()).unwrap();
+        assert_eq!(manifest.hooks.post_stow.as_deref(), Some("fc-cache"));
+        assert_eq!(manifest.hooks.pre_stow, None);
+
+        std::fs::write(tmp.path().join(FILE_NAME), "requires = ['fonts']\n").unwrap();
+        assert!(matches!(Manifest::read(tmp.path()), Err(Error::InvalidConfig(..))));
+    }
+}
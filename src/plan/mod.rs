@@ -0,0 +1,429 @@
+//! Planning of stow, unstow and restow operations.
+//!
+//! The planner computes every action needed for all requested packages
+//! before anything in the target is modified. It keeps track of the changes
+//! it has planned so far, so that later packages see the target as it will be
+//! once the earlier ones have been processed, and it collects every conflict
+//! instead of stopping at the first one.
+
+mod restow;
+mod stow;
+mod unstow;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::alternate::{base_path, Machine};
+use crate::error::{Error, IoAction, IoContext};
+use crate::fs::{
+    dotfiles_path, link_target_for, same_file_system, Action, InstallMode, LinkMode, Package, PathState, Symlink, Target,
+};
+use crate::ignore::IgnoreList;
+use crate::state::State;
+use crate::log::Log;
+use crate::template::Variables;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Stow,
+    Unstow,
+    Restow,
+}
+
+/// Settings that control how packages are planned.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Where messages about what is planned go.
+    pub log: Log,
+    /// Move files that are in the way of a link into the package instead of
+    /// reporting a conflict.
+    pub adopt: bool,
+    /// Link package entries named `dot-*` as `.*`.
+    pub dotfiles: bool,
+    /// How the targets of new links are written.
+    pub link_mode: LinkMode,
+    /// How package files are installed.
+    pub install_mode: InstallMode,
+    /// The variables that templates are rendered with.
+    pub variables: Variables,
+    /// Patterns ignored in every package, in addition to the package's own
+    /// ignore list.
+    pub ignore: IgnoreList,
+}
+
+/// The ordered actions for a set of packages, and the conflicts that prevent
+/// them from being executed.
+pub struct Plan {
+    pub actions: Vec<Action>,
+    pub conflicts: Vec<Error>,
+    /// For each action, the index of the package it was planned for, in the
+    /// order the packages were added.
+    pub owners: Vec<usize>,
+    /// Paths that unstowing leaves alone although the package has entries
+    /// for them.
+    pub skipped: Vec<Skipped>,
+}
+
+/// A path in the target that is not removed when unstowing a package.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Skipped {
+    pub path: PathBuf,
+    pub package: String,
+    pub reason: SkipReason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SkipReason {
+    /// A symlink that does not point into the package.
+    NotOwned,
+    /// A copy that was modified since it was installed.
+    Modified,
+    /// A file that was not installed from the package.
+    Foreign,
+    /// Not a symlink, and not of the same kind as the package entry.
+    NotALink,
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (path, package) = (&self.path, &self.package);
+        match self.reason {
+            SkipReason::NotOwned => write!(
+                f,
+                "error: Link path {:?} is not owned by package '{}'. not removing it.",
+                path, package
+            ),
+            SkipReason::Modified => write!(
+                f,
+                "warning: {:?} was modified since it was installed from package '{}'. not removing it.",
+                path, package
+            ),
+            SkipReason::Foreign => write!(
+                f,
+                "{:?} exists and was not installed from package '{}'. not removing it.",
+                path, package
+            ),
+            SkipReason::NotALink => write!(
+                f,
+                "{:?} exists and is not a directory or symlink. not removing it.",
+                path
+            ),
+        }
+    }
+}
+
+impl Plan {
+    /// Returns the indices of the actions planned for the package added
+    /// `package`th, in order.
+    pub fn actions_of(&self, package: usize) -> Vec<usize> {
+        (0..self.actions.len()).filter(|&index| self.owners[index] == package).collect()
+    }
+}
+
+pub struct Planner<'a> {
+    target_dir: &'a Path,
+    state: &'a State,
+    options: Options,
+    actions: Vec<Action>,
+    conflicts: Vec<Error>,
+    owners: Vec<usize>,
+    skipped: Vec<Skipped>,
+    /// The number of packages added so far.
+    packages: usize,
+    planned: HashMap<PathBuf, PathState>,
+    created_dirs: HashSet<PathBuf>,
+}
+
+impl<'a> Planner<'a> {
+    pub fn new<T: Target>(target: &'a T, state: &'a State, options: Options) -> Self {
+        Self {
+            target_dir: target.path(),
+            state,
+            options,
+            actions: Vec::new(),
+            conflicts: Vec::new(),
+            owners: Vec::new(),
+            skipped: Vec::new(),
+            packages: 0,
+            planned: HashMap::new(),
+            created_dirs: HashSet::new(),
+        }
+    }
+
+    /// Adds the actions for applying `operation` to `package` to the plan.
+    pub fn add<P: Package>(&mut self, operation: Operation, package: &P) -> Result<(), Error> {
+        self.options.log.write(format_args!(
+            "plan: {:?} package {} from {:?}",
+            operation,
+            package.name(),
+            package.path()
+        ));
+        if self.options.install_mode == InstallMode::Hardlink
+            && operation != Operation::Unstow
+            && !same_file_system(package.path(), self.target_dir)?
+        {
+            return Err(Error::HardlinkAcrossFileSystems {
+                package: package.path().to_path_buf(),
+                target: self.target_dir.to_path_buf(),
+            });
+        }
+        self.packages += 1;
+        let result = match operation {
+            Operation::Stow => self.stow(package),
+            Operation::Unstow => self.unstow(package),
+            Operation::Restow => self.restow(package),
+        };
+        result.map_err(|e| e.in_package(package.name()))
+    }
+
+    pub fn finish(self) -> Plan {
+        Plan {
+            actions: self.actions,
+            conflicts: self.conflicts,
+            owners: self.owners,
+            skipped: self.skipped,
+        }
+    }
+
+    /// Returns the path in the target for `item`, a path relative to the package root.
+    fn target_path_of(&self, item: &Path) -> PathBuf {
+        let item = base_path(item);
+        if self.options.dotfiles {
+            self.target_dir.join(dotfiles_path(&item))
+        } else {
+            self.target_dir.join(item)
+        }
+    }
+
+    /// Returns the name that the package entry `name` is linked as.
+    fn target_name_of(&self, name: &OsStr) -> PathBuf {
+        let name = base_path(Path::new(name));
+        if self.options.dotfiles {
+            dotfiles_path(&name)
+        } else {
+            name
+        }
+    }
+
+    /// Returns the machine that alternates are selected for.
+    fn machine(&self) -> Machine {
+        Machine::of(&self.options.variables)
+    }
+
+    /// Returns the target for a new symlink at `link_path` that points to `source`.
+    fn link_target(&self, source: &Path, link_path: &Path) -> Result<PathBuf, Error> {
+        link_target_for(source, link_path, self.options.link_mode)
+    }
+
+    /// Returns the action that installs the package file `source` at `path`
+    /// when files are copied or hard linked.
+    fn install_action(&self, source: PathBuf, path: PathBuf) -> Action {
+        match self.options.install_mode {
+            InstallMode::Hardlink => Action::Hardlink { source, path },
+            InstallMode::Copy | InstallMode::Symlink => Action::Copy { source, path },
+        }
+    }
+
+    /// Returns what will occupy `path` once the actions planned so far are executed.
+    fn state_of(&self, path: &Path) -> Result<PathState, Error> {
+        if let Some(state) = self.planned.get(path) {
+            return Ok(state.clone());
+        }
+        // Below a link that is planned to be replaced, the target only
+        // contains what is planned, not what the link points to.
+        for ancestor in path.ancestors().skip(1).take_while(|ancestor| ancestor.starts_with(self.target_dir)) {
+            if self.planned.contains_key(ancestor) && PathState::of(ancestor)? != PathState::Dir {
+                return Ok(PathState::Missing);
+            }
+        }
+        PathState::of(path)
+    }
+
+    /// Returns the entries that `dir` will contain once the actions planned
+    /// so far are executed.
+    fn entries_of(&self, dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut entries = BTreeSet::new();
+        if dir.is_dir() {
+            for entry in dir.read_dir().at(IoAction::ReadDir, dir)? {
+                entries.insert(entry.at(IoAction::ReadDir, dir)?.path());
+            }
+        }
+        entries.extend(self.planned.keys().filter(|path| path.parent() == Some(dir)).cloned());
+
+        let mut remaining = Vec::new();
+        for path in entries {
+            if self.state_of(&path)? != PathState::Missing {
+                remaining.push(path);
+            }
+        }
+        Ok(remaining)
+    }
+
+    /// Returns true if `dir` was, or will be, created by syra.
+    fn is_created_dir(&self, dir: &Path) -> bool {
+        self.created_dirs.contains(dir) || self.state.is_created_dir(dir)
+    }
+
+    fn conflict(&mut self, conflict: Error) {
+        self.options.log.write(format_args!("plan: conflict: {}", conflict));
+        self.conflicts.push(conflict);
+    }
+
+    fn plan(&mut self, action: Action) {
+        self.plan_for(self.current_package(), action);
+    }
+
+    /// Returns the index of the package being added.
+    fn current_package(&self) -> usize {
+        self.packages.saturating_sub(1)
+    }
+
+    /// Plans `action` for the package added `package`th.
+    fn plan_for(&mut self, package: usize, action: Action) {
+        self.track(&action);
+        self.actions.push(action);
+        self.owners.push(package);
+    }
+
+    /// Updates the overlay of planned changes with `action`.
+    fn track(&mut self, action: &Action) {
+        self.options.log.write(format_args!("plan: {}", action));
+        match action {
+            Action::Unlink(path)
+            | Action::RemoveDir(path)
+            | Action::RemoveFile(path)
+            | Action::Adopt(Symlink { path, .. }) => {
+                self.planned.insert(path.clone(), PathState::Missing);
+            }
+            Action::CreateDir(path) | Action::Unfold(path) => {
+                self.planned.insert(path.clone(), PathState::Dir);
+                self.created_dirs.insert(path.clone());
+            }
+            Action::Link(Symlink { path, target }) => {
+                self.planned.insert(path.clone(), PathState::Symlink(target.clone()));
+            }
+            Action::Copy { path, .. } | Action::Hardlink { path, .. } | Action::Render { path, .. } => {
+                self.planned.insert(path.clone(), PathState::File);
+            }
+            Action::Rename { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::exec::Executor;
+    use crate::fs::{PackageImpl, TargetImpl};
+    use std::fs::{create_dir_all, write};
+
+    pub fn create_files(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            write(&path, file).unwrap();
+        }
+    }
+
+    pub fn setup() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        let stow_dir = root.join("stow");
+        let target_dir = root.join("target");
+        create_dir_all(&stow_dir).unwrap();
+        create_dir_all(&target_dir).unwrap();
+        (tmp, stow_dir, target_dir)
+    }
+
+    pub fn plan(stow_dir: &Path, target_dir: &Path, operations: &[(Operation, &str)]) -> Result<Plan, Error> {
+        plan_with(Options::default(), stow_dir, target_dir, operations)
+    }
+
+    pub fn plan_with(
+        options: Options,
+        stow_dir: &Path,
+        target_dir: &Path,
+        operations: &[(Operation, &str)],
+    ) -> Result<Plan, Error> {
+        let target = TargetImpl::new(target_dir)?;
+        let state = State::load(target_dir)?;
+        let mut planner = Planner::new(&target, &state, options);
+        for (operation, pkg) in operations {
+            let package = PackageImpl::new(stow_dir, pkg)?
+                .ignoring(&planner.options.ignore)
+                .selecting(planner.machine());
+            planner.add(*operation, &package)?;
+        }
+        Ok(planner.finish())
+    }
+
+    /// Plans and executes the operations, failing on any conflict.
+    pub fn run(stow_dir: &Path, target_dir: &Path, operations: &[(Operation, &str)]) -> Result<Vec<Action>, Error> {
+        run_with(Options::default(), stow_dir, target_dir, operations)
+    }
+
+    pub fn run_with(
+        options: Options,
+        stow_dir: &Path,
+        target_dir: &Path,
+        operations: &[(Operation, &str)],
+    ) -> Result<Vec<Action>, Error> {
+        let plan = plan_with(options, stow_dir, target_dir, operations)?;
+        if !plan.conflicts.is_empty() {
+            return Err(Error::Conflicts(plan.conflicts));
+        }
+        let mut state = State::load(target_dir)?;
+        Executor::new(&mut state, Log::default()).execute(&plan.actions)?;
+        state.save()?;
+        Ok(plan.actions)
+    }
+
+    #[test]
+    fn test_conflicts_of_all_packages_are_reported() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.profile", "b/.vimrc", "c/.inputrc"]);
+        create_files(&target_dir, &[".bashrc", ".profile", ".vimrc"]);
+
+        let plan = plan(
+            &stow_dir,
+            &target_dir,
+            &[(Operation::Stow, "a"), (Operation::Stow, "b"), (Operation::Stow, "c")],
+        )
+        .unwrap();
+
+        assert_eq!(plan.conflicts.len(), 3);
+        assert!(matches!(&plan.conflicts[2], Error::LinkPathExists(path, pkg)
+            if path == &target_dir.join(".vimrc") && pkg == "b"));
+    }
+
+    #[test]
+    fn test_later_packages_see_planned_changes() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/a.conf", "b/.config/b.conf"]);
+
+        let plan = plan(&stow_dir, &target_dir, &[(Operation::Stow, "a"), (Operation::Stow, "b")]).unwrap();
+
+        assert!(plan.conflicts.is_empty());
+        let planned: Vec<String> = plan.actions.iter().map(|action| action.to_string()).collect();
+        assert_eq!(
+            planned,
+            vec![
+                format!("mkdir({:?})", target_dir.join(".config")),
+                format!(
+                    "symlink({:?}, {:?})",
+                    target_dir.join(".config/a.conf"),
+                    PathBuf::from("../../stow/a/.config/a.conf")
+                ),
+                format!(
+                    "symlink({:?}, {:?})",
+                    target_dir.join(".config/b.conf"),
+                    PathBuf::from("../../stow/b/.config/b.conf")
+                ),
+            ]
+        );
+        // Nothing is touched while planning.
+        assert_eq!(target_dir.read_dir().unwrap().count(), 0);
+    }
+}
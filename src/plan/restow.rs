@@ -0,0 +1,192 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, IoAction, IoContext};
+use crate::fs::{content_hash, resolve_link, Action, Package, PathState};
+
+use super::Planner;
+
+impl Planner<'_> {
+    /// Brings the target in line with the current contents of the package.
+    ///
+    /// Links that are already in place are left untouched, links to files
+    /// that were removed from the package are deleted, and links for new
+    /// files are created, so re-stowing never removes a link that is still
+    /// valid. Directories that syra created and that are left empty are
+    /// removed, deepest first.
+    pub(super) fn restow<P: Package>(&mut self, package: &P) -> Result<(), Error> {
+        let mut dirs = BTreeSet::new();
+        for path in self.find_stale_links(package)? {
+            dirs.extend(self.dirs_above(&path));
+            self.plan(Action::Unlink(path));
+        }
+        for path in self.find_stale_copies(package)? {
+            dirs.extend(self.dirs_above(&path));
+            self.plan(Action::RemoveFile(path));
+        }
+        for dir in dirs.iter().rev() {
+            self.clean_up_dir(package, dir)?;
+        }
+        self.stow(package)
+    }
+
+    /// Returns the directories of the target that contain `path`.
+    fn dirs_above(&self, path: &Path) -> Vec<PathBuf> {
+        path.ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(self.target_dir) && *dir != self.target_dir)
+            .map(Path::to_path_buf)
+            .collect()
+    }
+
+    /// Returns the links in the target that point into the package at
+    /// something that no longer exists or is now ignored.
+    ///
+    /// The target directories of directories that exist in the package are
+    /// searched, as those are the directories of the target that stowing
+    /// the package descends into, along with the directories syra created,
+    /// which may hold the links of directories removed from the package.
+    fn find_stale_links<P: Package>(&self, package: &P) -> Result<Vec<PathBuf>, Error> {
+        let mut dirs = BTreeSet::from([self.target_dir.to_path_buf()]);
+        let mut walk = package.walk();
+        while let Some(entry) = walk.next() {
+            let entry = entry?;
+            if !entry.is_dir() {
+                continue;
+            }
+            let dir = self.target_path_of(&entry.path);
+            if PathState::of(&dir)? == PathState::Dir {
+                dirs.insert(dir);
+            } else {
+                walk.skip_dir();
+            }
+        }
+        dirs.extend(self.state.created_dirs());
+
+        let mut stale = Vec::new();
+        for dir in dirs {
+            stale.extend(self.stale_links_in(package, &dir)?);
+        }
+        Ok(stale)
+    }
+
+    /// Returns the unmodified copies of files that were removed from the
+    /// package or are now ignored.
+    fn find_stale_copies<P: Package>(&self, package: &P) -> Result<Vec<PathBuf>, Error> {
+        let mut stale = Vec::new();
+        for (path, copy) in self.state.copies() {
+            let Ok(item) = copy.source.strip_prefix(package.path()) else {
+                continue;
+            };
+            if (package.is_ignored(item) || PathState::of(&copy.source)? == PathState::Missing)
+                && PathState::of(&path)? == PathState::File
+                && content_hash(&path)? == copy.hash
+            {
+                stale.push(path);
+            }
+        }
+        Ok(stale)
+    }
+
+    /// Returns the stale links directly inside `target_subdir`, a directory
+    /// of the target, if it is a real directory.
+    fn stale_links_in<P: Package>(&self, package: &P, target_subdir: &Path) -> Result<Vec<PathBuf>, Error> {
+        if PathState::of(target_subdir)? != PathState::Dir {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+        for entry in target_subdir.read_dir().at(IoAction::ReadDir, target_subdir)? {
+            entries.push(entry.at(IoAction::ReadDir, target_subdir)?.path());
+        }
+        entries.sort();
+
+        let mut stale = Vec::new();
+        for link_path in entries {
+            let PathState::Symlink(link_target) = PathState::of(&link_path)? else {
+                continue;
+            };
+            let resolved = resolve_link(&link_path, &link_target);
+            let Ok(item) = resolved.strip_prefix(package.path()) else {
+                continue;
+            };
+            if package.is_ignored(item) || PathState::of(&resolved)? == PathState::Missing {
+                stale.push(link_path);
+            }
+        }
+
+        Ok(stale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{create_files, run, setup};
+    use super::super::Operation::{Restow, Stow};
+    use super::*;
+    use std::fs::{read_link, remove_dir_all, remove_file};
+
+    #[test]
+    fn test_restow_only_changes_what_changed() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.profile", "a/.config/nvim/init.vim"]);
+        create_files(&target_dir, &[".config/user.conf"]);
+
+        run(&stow_dir, &target_dir, &[(Restow, "a")]).unwrap();
+        assert!(target_dir.join(".profile").is_symlink());
+
+        remove_file(stow_dir.join("a/.profile")).unwrap();
+        create_files(&stow_dir, &["a/.inputrc"]);
+
+        let actions = run(&stow_dir, &target_dir, &[(Restow, "a")]).unwrap();
+        let planned: Vec<String> = actions.iter().map(|action| action.to_string()).collect();
+        assert_eq!(
+            planned,
+            vec![
+                format!("unlink({:?})", target_dir.join(".profile")),
+                format!("symlink({:?}, {:?})", target_dir.join(".inputrc"), PathBuf::from("../stow/a/.inputrc")),
+            ]
+        );
+        assert!(!target_dir.join(".profile").is_symlink());
+        assert_eq!(read_link(target_dir.join(".bashrc")).unwrap(), PathBuf::from("../stow/a/.bashrc"));
+        assert_eq!(
+            read_link(target_dir.join(".config/nvim")).unwrap(),
+            PathBuf::from("../../stow/a/.config/nvim")
+        );
+    }
+
+    #[test]
+    fn test_restow_removes_stale_links_in_nested_directories() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/app/keep.conf", "a/.config/app/old.conf"]);
+        create_files(&target_dir, &[".config/app/user.conf"]);
+
+        run(&stow_dir, &target_dir, &[(Restow, "a")]).unwrap();
+        remove_file(stow_dir.join("a/.config/app/old.conf")).unwrap();
+
+        let actions = run(&stow_dir, &target_dir, &[(Restow, "a")]).unwrap();
+
+        assert_eq!(actions.len(), 1);
+        assert!(!target_dir.join(".config/app/old.conf").is_symlink());
+        assert!(target_dir.join(".config/app/keep.conf").is_symlink());
+        assert!(target_dir.join(".config/app/user.conf").is_file());
+    }
+
+    #[test]
+    fn test_restow_cleans_up_removed_package_directories() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/app/rc", "b/.config/other/rc"]);
+        create_files(&stow_dir, &["c/.local/share/c/file", "d/.profile", "d/.local/share/d/file"]);
+        run(&stow_dir, &target_dir, &[(Stow, "a"), (Stow, "b"), (Stow, "c"), (Stow, "d")]).unwrap();
+        assert!(target_dir.join(".config").is_dir() && !target_dir.join(".config").is_symlink());
+        assert!(target_dir.join(".local/share").is_dir() && !target_dir.join(".local/share").is_symlink());
+
+        remove_dir_all(stow_dir.join("a/.config")).unwrap();
+        remove_dir_all(stow_dir.join("d/.local")).unwrap();
+        run(&stow_dir, &target_dir, &[(Restow, "a"), (Restow, "d")]).unwrap();
+
+        assert!(!target_dir.join(".config/app").is_symlink());
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), PathBuf::from("../stow/b/.config"));
+        assert_eq!(read_link(target_dir.join(".local")).unwrap(), PathBuf::from("../stow/c/.local"));
+    }
+}
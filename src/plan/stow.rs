@@ -0,0 +1,639 @@
+use std::path::{Path, PathBuf};
+
+use crate::alternate::is_alternate;
+use crate::error::Error;
+use crate::fs::{
+    content_hash, hash_of, links_to, resolve_link, Action, Entry, EntryKind, InstallMode, Package, PackageImpl, PathState, Symlink,
+};
+use crate::state::FileOrigin;
+use crate::template::render;
+
+use super::unstow::is_owned_by_package;
+use super::Planner;
+
+impl Planner<'_> {
+    pub(super) fn stow<P: Package>(&mut self, package: &P) -> Result<(), Error> {
+        let mut walk = package.walk();
+        while let Some(entry) = walk.next() {
+            if !self.stow_entry(package, &entry?)? {
+                walk.skip_dir();
+            }
+        }
+        Ok(())
+    }
+
+    /// Stows `entry`, and returns true if the entries inside it have to be
+    /// stowed one by one as well.
+    fn stow_entry<P: Package>(&mut self, package: &P, entry: &Entry) -> Result<bool, Error> {
+        if entry.kind == EntryKind::File && package.is_template(&entry.path) {
+            self.render_entry(package, entry)?;
+            return Ok(false);
+        }
+        if self.options.install_mode != InstallMode::Symlink && matches!(entry.kind, EntryKind::File | EntryKind::Dir) {
+            return self.install_entry(package, entry);
+        }
+
+        let link_path = self.target_path_of(&entry.path);
+        let source = package.path().join(&entry.path);
+        let link_target = self.link_target(&source, &link_path)?;
+        if is_alternate(&entry.path) {
+            self.options.log.write(format_args!("plan: selected alternate {:?} for {:?}", entry.path, link_path));
+        }
+
+        match self.state_of(&link_path)? {
+            PathState::Missing if entry.is_dir() && self.contains_templates(package, &entry.path)? => {
+                // A link to the directory would expose its templates.
+                self.plan(Action::CreateDir(link_path));
+                return Ok(true);
+            }
+            PathState::Missing => {
+                // The path is free, so link the item as a whole. For a
+                // directory this folds the whole tree into a single link.
+                self.plan(Action::Link(Symlink {
+                    path: link_path,
+                    target: link_target,
+                }));
+            }
+            PathState::Dir if entry.is_dir() => {
+                // A real directory already exists, so link the contents of
+                // the package directory into it.
+                return Ok(true);
+            }
+            PathState::Symlink(existing_target)
+                if is_owned_by_package(package, &entry.path, &link_path, &existing_target) =>
+            {
+                if entry.is_dir() && self.contains_templates(package, &entry.path)? {
+                    // Templates were added since the directory was folded.
+                    self.plan(Action::Unfold(link_path));
+                    return Ok(true);
+                }
+                if !links_to(&link_path, &existing_target, &source) {
+                    // Another alternate was selected when it was linked.
+                    self.plan(Action::Unlink(link_path.clone()));
+                    self.plan(Action::Link(Symlink {
+                        path: link_path,
+                        target: link_target,
+                    }));
+                    return Ok(false);
+                }
+                self.options.log.write(format_args!(
+                    "symlink({:?}, {:?}) already exists and points to the same target",
+                    link_path, link_target
+                ));
+            }
+            PathState::Symlink(existing_target) => {
+                match self.foldable_dir_of_other_package(package, entry, &link_path, &existing_target) {
+                    Some(other) => {
+                        self.unfold(&link_path, other)?;
+                        return Ok(true);
+                    }
+                    None => self.conflict(Error::LinkNotOwnedByPackage(link_path, package.name().to_string())),
+                }
+            }
+            PathState::File if entry.kind == EntryKind::File && self.is_installed(&link_path, &source)? => {
+                // The file was copied or hard linked by an earlier run with
+                // another install mode.
+                self.plan(Action::RemoveFile(link_path.clone()));
+                self.plan(Action::Link(Symlink {
+                    path: link_path,
+                    target: link_target,
+                }));
+            }
+            PathState::File if self.options.adopt && entry.kind == EntryKind::File => {
+                self.plan(Action::Adopt(Symlink {
+                    path: link_path.clone(),
+                    target: source,
+                }));
+                self.plan(Action::Link(Symlink {
+                    path: link_path,
+                    target: link_target,
+                }));
+            }
+            PathState::Dir | PathState::File => {
+                self.conflict(Error::LinkPathExists(link_path, package.name().to_string()));
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Installs `entry` by copying or hard linking it, and returns true if
+    /// it is a directory. Directories are never folded, they are created in
+    /// the target and their entries are installed one by one.
+    fn install_entry<P: Package>(&mut self, package: &P, entry: &Entry) -> Result<bool, Error> {
+        let path = self.target_path_of(&entry.path);
+        let source = package.path().join(&entry.path);
+
+        match self.state_of(&path)? {
+            PathState::Missing if entry.is_dir() => self.plan(Action::CreateDir(path)),
+            PathState::Missing => self.plan(self.install_action(source, path)),
+            PathState::Dir if entry.is_dir() => {}
+            PathState::Symlink(existing_target)
+                if is_owned_by_package(package, &entry.path, &path, &existing_target) =>
+            {
+                // Replace the link of an earlier run in symlink mode.
+                self.plan(Action::Unlink(path.clone()));
+                if entry.is_dir() {
+                    self.plan(Action::CreateDir(path));
+                } else {
+                    self.plan(self.install_action(source, path));
+                }
+            }
+            PathState::Symlink(existing_target) => {
+                match self.foldable_dir_of_other_package(package, entry, &path, &existing_target) {
+                    Some(other) => self.unfold(&path, other)?,
+                    None => {
+                        self.conflict(Error::LinkNotOwnedByPackage(path, package.name().to_string()));
+                        return Ok(false);
+                    }
+                }
+            }
+            PathState::File if !entry.is_dir() => match self.state.origin_of(&path, &source)? {
+                FileOrigin::Hardlink if self.options.install_mode == InstallMode::Hardlink => {}
+                FileOrigin::Copy
+                    if self.options.install_mode == InstallMode::Copy
+                        && content_hash(&path)? == content_hash(&source)? => {}
+                FileOrigin::Hardlink | FileOrigin::Copy => {
+                    // Installed with another mode, or the package file
+                    // changed since it was copied.
+                    self.plan(Action::RemoveFile(path.clone()));
+                    self.plan(self.install_action(source, path));
+                }
+                FileOrigin::ModifiedCopy | FileOrigin::Foreign if self.options.adopt => {
+                    self.plan(Action::Adopt(Symlink {
+                        path: path.clone(),
+                        target: source.clone(),
+                    }));
+                    self.plan(self.install_action(source, path));
+                }
+                FileOrigin::ModifiedCopy | FileOrigin::Foreign => {
+                    self.conflict(Error::LinkPathExists(path, package.name().to_string()));
+                }
+            },
+            PathState::Dir | PathState::File => {
+                self.conflict(Error::LinkPathExists(path, package.name().to_string()));
+                return Ok(false);
+            }
+        }
+
+        Ok(entry.is_dir())
+    }
+
+    /// Renders the template `entry` and installs the result, replacing an
+    /// earlier rendering that is out of date.
+    fn render_entry<P: Package>(&mut self, package: &P, entry: &Entry) -> Result<(), Error> {
+        let path = self.target_path_of(&package.installed_path(entry));
+        let source = package.path().join(&entry.path);
+        let content = render(&source, &self.options.variables)?;
+        let hash = hash_of(content.as_bytes());
+        let render = Action::Render {
+            source: source.clone(),
+            path: path.clone(),
+            content,
+        };
+
+        match self.state_of(&path)? {
+            PathState::Missing => self.plan(render),
+            PathState::Symlink(existing_target)
+                if is_owned_by_package(package, &entry.path, &path, &existing_target) =>
+            {
+                // The file was linked before it became a template.
+                self.plan(Action::Unlink(path));
+                self.plan(render);
+            }
+            PathState::Symlink(_) => {
+                self.conflict(Error::LinkNotOwnedByPackage(path, package.name().to_string()));
+            }
+            PathState::File => match self.state.origin_of(&path, &source)? {
+                FileOrigin::Copy if content_hash(&path)? == hash => {}
+                FileOrigin::Copy | FileOrigin::Hardlink => {
+                    self.plan(Action::RemoveFile(path));
+                    self.plan(render);
+                }
+                FileOrigin::ModifiedCopy | FileOrigin::Foreign => {
+                    self.conflict(Error::LinkPathExists(path, package.name().to_string()));
+                }
+            },
+            PathState::Dir => self.conflict(Error::LinkPathExists(path, package.name().to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the package directory `dir` contains a template.
+    fn contains_templates<P: Package>(&self, package: &P, dir: &Path) -> Result<bool, Error> {
+        for entry in package.read_dir(dir)? {
+            let found = match entry.kind {
+                EntryKind::File => package.is_template(&entry.path),
+                EntryKind::Dir => self.contains_templates(package, &entry.path)?,
+                EntryKind::Symlink(_) | EntryKind::Special => false,
+            };
+            if found {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns true if the file at `path` was copied or hard linked from the
+    /// package file `source` and is unmodified.
+    fn is_installed(&self, path: &Path, source: &Path) -> Result<bool, Error> {
+        Ok(matches!(self.state.origin_of(path, source)?, FileOrigin::Hardlink | FileOrigin::Copy))
+    }
+
+    /// Returns the package owning the directory that the symlink at
+    /// `link_path` points to, along with the directory's path relative to
+    /// that package, if the link can be unfolded to make room for `entry`.
+    fn foldable_dir_of_other_package<P: Package>(
+        &self,
+        package: &P,
+        entry: &Entry,
+        link_path: &Path,
+        existing_target: &Path,
+    ) -> Option<(PackageImpl, PathBuf)> {
+        let stow_dir = package.path().parent()?;
+        let resolved = resolve_link(link_path, existing_target);
+        if !entry.is_dir() || !resolved.is_dir() || resolved.starts_with(package.path()) {
+            return None;
+        }
+
+        PackageImpl::containing(stow_dir, &resolved, &self.options.ignore, &self.machine())
+    }
+
+    /// Replaces the directory symlink at `link_path` with a real directory
+    /// containing a link to each entry of the directory it pointed to.
+    fn unfold(&mut self, link_path: &Path, (other, dir): (PackageImpl, PathBuf)) -> Result<(), Error> {
+        self.options.log.write(format_args!("plan: Unfolding {:?} owned by {:?}", link_path, other.path()));
+
+        // The link may have been planned earlier, in which case a directory
+        // is created in its place instead. The directory and its links stay
+        // with the package that planned the link, so that they are executed
+        // and rolled back along with it.
+        let planned_link = self.actions.iter().position(|action| {
+            matches!(action, Action::Link(Symlink { path, .. }) if path == link_path)
+        });
+        let owner = match planned_link {
+            Some(index) => {
+                let create_dir = Action::CreateDir(link_path.to_path_buf());
+                self.track(&create_dir);
+                self.actions[index] = create_dir;
+                self.owners[index]
+            }
+            None => {
+                self.plan(Action::Unfold(link_path.to_path_buf()));
+                self.current_package()
+            }
+        };
+
+        for child in other.read_dir(&dir)? {
+            let child_link = link_path.join(self.target_name_of(child.path.file_name().unwrap_or_default()));
+            let child_target = self.link_target(&other.path().join(&child.path), &child_link)?;
+            self.plan_for(
+                owner,
+                Action::Link(Symlink {
+                    path: child_link,
+                    target: child_target,
+                }),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{create_files, run, run_with, setup};
+    use super::super::Operation::Stow;
+    use super::super::Options;
+    use crate::fs::{InstallMode, LinkMode};
+    use crate::ignore::IgnoreList;
+    use crate::template::Variables;
+    use super::*;
+    use std::fs::{read_link, read_to_string, write};
+
+    #[test]
+    fn test_stow_folds_directory_when_path_is_free() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/nvim/init.vim"]);
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+
+        assert_eq!(read_link(target_dir.join(".bashrc")).unwrap(), PathBuf::from("../stow/a/.bashrc"));
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), PathBuf::from("../stow/a/.config"));
+    }
+
+    #[test]
+    fn test_stow_descends_into_existing_directory() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/nvim/init.vim"]);
+        create_files(&target_dir, &[".config/user.conf"]);
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+
+        assert!(!target_dir.join(".config").is_symlink());
+        assert_eq!(
+            read_link(target_dir.join(".config/nvim")).unwrap(),
+            PathBuf::from("../../stow/a/.config/nvim")
+        );
+    }
+
+    #[test]
+    fn test_stow_unfolds_directory_of_other_package() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/nvim/init.vim", "b/.config/fish/config.fish"]);
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        let actions = run(&stow_dir, &target_dir, &[(Stow, "b")]).unwrap();
+
+        assert!(matches!(&actions[0], Action::Unfold(path) if path == &target_dir.join(".config")));
+        assert!(!target_dir.join(".config").is_symlink());
+        assert!(target_dir.join(".config").is_dir());
+        assert_eq!(
+            read_link(target_dir.join(".config/nvim")).unwrap(),
+            PathBuf::from("../../stow/a/.config/nvim")
+        );
+        assert_eq!(
+            read_link(target_dir.join(".config/fish")).unwrap(),
+            PathBuf::from("../../stow/b/.config/fish")
+        );
+    }
+
+    #[test]
+    fn test_stow_unfolds_nested_directories() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/shared/a.conf", "b/.config/shared/b.conf"]);
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        run(&stow_dir, &target_dir, &[(Stow, "b")]).unwrap();
+
+        assert!(!target_dir.join(".config/shared").is_symlink());
+        assert_eq!(
+            read_link(target_dir.join(".config/shared/a.conf")).unwrap(),
+            PathBuf::from("../../../stow/a/.config/shared/a.conf")
+        );
+        assert_eq!(
+            read_link(target_dir.join(".config/shared/b.conf")).unwrap(),
+            PathBuf::from("../../../stow/b/.config/shared/b.conf")
+        );
+    }
+
+    #[test]
+    fn test_stow_twice_plans_nothing() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/nvim/init.vim"]);
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        let actions = run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn test_stow_conflicts_with_existing_file() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc"]);
+        create_files(&target_dir, &[".bashrc"]);
+
+        match run(&stow_dir, &target_dir, &[(Stow, "a")]) {
+            Err(Error::Conflicts(conflicts)) => {
+                assert!(matches!(&conflicts[..], [Error::LinkPathExists(path, _)] if path == &target_dir.join(".bashrc")))
+            }
+            other => panic!("Expected a conflict, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_stow_conflicts_with_same_file_in_other_package() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/app.conf", "b/.config/app.conf"]);
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+
+        match run(&stow_dir, &target_dir, &[(Stow, "b")]) {
+            Err(Error::Conflicts(conflicts)) => {
+                assert!(matches!(&conflicts[..], [Error::LinkNotOwnedByPackage(path, pkg)]
+                    if path == &target_dir.join(".config/app.conf") && pkg == "b"))
+            }
+            other => panic!("Expected a conflict, got {:?}", other.map(|_| ())),
+        }
+        // Nothing is changed when the plan has conflicts.
+        assert!(target_dir.join(".config").is_symlink());
+    }
+
+    #[test]
+    fn test_stow_adopts_existing_file() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/app.conf"]);
+        create_files(&target_dir, &[".config/app.conf"]);
+        write(target_dir.join(".config/app.conf"), "machine local").unwrap();
+
+        let options = Options {
+            adopt: true,
+            ..Default::default()
+        };
+        run_with(options, &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+
+        assert_eq!(
+            read_link(target_dir.join(".config/app.conf")).unwrap(),
+            PathBuf::from("../../stow/a/.config/app.conf")
+        );
+        assert_eq!(read_to_string(stow_dir.join("a/.config/app.conf")).unwrap(), "machine local");
+        assert_eq!(stow_dir.join("a/.config").read_dir().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_stow_skips_ignored_entries() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(
+            &stow_dir,
+            &["a/.git/HEAD", "a/README.md", "a/.bashrc", "a/.bashrc~", "a/.profile.bak"],
+        );
+
+        let options = Options {
+            ignore: IgnoreList::new(&["glob:*.bak"]).unwrap(),
+            ..Default::default()
+        };
+        run_with(options, &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+
+        let mut linked: Vec<_> = target_dir.read_dir().unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        linked.sort();
+        assert_eq!(linked, vec![".bashrc"]);
+    }
+
+    #[test]
+    fn test_stow_dotfiles() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/dot-bashrc", "a/dot-config/nvim/init.vim", "b/dot-config/fish/config.fish"]);
+
+        let dotfiles = Options {
+            dotfiles: true,
+            ..Default::default()
+        };
+        run_with(dotfiles.clone(), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert_eq!(read_link(target_dir.join(".bashrc")).unwrap(), PathBuf::from("../stow/a/dot-bashrc"));
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), PathBuf::from("../stow/a/dot-config"));
+
+        run_with(dotfiles, &stow_dir, &target_dir, &[(Stow, "b")]).unwrap();
+        assert_eq!(
+            read_link(target_dir.join(".config/nvim")).unwrap(),
+            PathBuf::from("../../stow/a/dot-config/nvim")
+        );
+        assert_eq!(
+            read_link(target_dir.join(".config/fish")).unwrap(),
+            PathBuf::from("../../stow/b/dot-config/fish")
+        );
+    }
+
+    #[test]
+    fn test_stow_absolute_links() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/nvim/init.vim", "b/.config/fish/config.fish"]);
+
+        let absolute = Options {
+            link_mode: LinkMode::Absolute,
+            ..Default::default()
+        };
+        run_with(absolute.clone(), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert_eq!(read_link(target_dir.join(".bashrc")).unwrap(), stow_dir.join("a/.bashrc"));
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), stow_dir.join("a/.config"));
+
+        run_with(absolute, &stow_dir, &target_dir, &[(Stow, "b")]).unwrap();
+        assert_eq!(read_link(target_dir.join(".config/nvim")).unwrap(), stow_dir.join("a/.config/nvim"));
+        assert_eq!(read_link(target_dir.join(".config/fish")).unwrap(), stow_dir.join("b/.config/fish"));
+
+        // Links of either style are owned, whatever the mode.
+        assert!(super::super::tests::plan(&stow_dir, &target_dir, &[(Stow, "a"), (Stow, "b")])
+            .unwrap()
+            .actions
+            .is_empty());
+        run(&stow_dir, &target_dir, &[(super::super::Operation::Unstow, "a")]).unwrap();
+        assert!(!target_dir.join(".bashrc").exists());
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), PathBuf::from("../stow/b/.config"));
+    }
+
+    #[test]
+    fn test_stow_copies() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/nvim/init.vim"]);
+        let copy = Options {
+            install_mode: InstallMode::Copy,
+            ..Default::default()
+        };
+
+        run_with(copy.clone(), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert!(!target_dir.join(".config").is_symlink());
+        assert_eq!(read_to_string(target_dir.join(".config/nvim/init.vim")).unwrap(), "a/.config/nvim/init.vim");
+        assert!(run_with(copy.clone(), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap().is_empty());
+
+        // Changes to the package are copied again.
+        write(stow_dir.join("a/.bashrc"), "changed").unwrap();
+        run_with(copy.clone(), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert_eq!(read_to_string(target_dir.join(".bashrc")).unwrap(), "changed");
+
+        // Copies that were changed in the target are not overwritten.
+        write(target_dir.join(".bashrc"), "local").unwrap();
+        write(stow_dir.join("a/.bashrc"), "changed again").unwrap();
+        assert!(matches!(run_with(copy, &stow_dir, &target_dir, &[(Stow, "a")]), Err(Error::Conflicts(_))));
+        assert_eq!(read_to_string(target_dir.join(".bashrc")).unwrap(), "local");
+    }
+
+    #[test]
+    fn test_stow_switches_install_mode() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/app.conf"]);
+        let hardlink = Options {
+            install_mode: InstallMode::Hardlink,
+            ..Default::default()
+        };
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        run_with(hardlink, &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert!(crate::fs::same_file(&target_dir.join(".bashrc"), &stow_dir.join("a/.bashrc")).unwrap());
+        assert!(target_dir.join(".config").is_dir() && !target_dir.join(".config").is_symlink());
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert_eq!(read_link(target_dir.join(".bashrc")).unwrap(), PathBuf::from("../stow/a/.bashrc"));
+        assert_eq!(read_link(target_dir.join(".config/app.conf")).unwrap(), PathBuf::from("../../stow/a/.config/app.conf"));
+    }
+
+    #[test]
+    fn test_stow_renders_templates() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.ssh/known_hosts", "b/.ssh/config"]);
+        write(stow_dir.join("a/.ssh/config.tmpl"), "User {{ vars.user }}\n").unwrap();
+        write(stow_dir.join("a/.syra-templates"), "^/\\.ssh/config$\n").unwrap();
+        let options = |user: &str| Options {
+            variables: Variables::new(&[format!("user={}", user)]).unwrap(),
+            ..Default::default()
+        };
+
+        run_with(options("me"), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        // The directory is not folded, so the template is not exposed.
+        assert!(!target_dir.join(".ssh").is_symlink());
+        assert!(!target_dir.join(".ssh/config.tmpl").exists());
+        assert_eq!(read_to_string(target_dir.join(".ssh/config")).unwrap(), "User me\n");
+        assert!(target_dir.join(".ssh/known_hosts").is_symlink());
+        assert!(run_with(options("me"), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap().is_empty());
+
+        run_with(options("you"), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert_eq!(read_to_string(target_dir.join(".ssh/config")).unwrap(), "User you\n");
+
+        // A rendered file that was changed is neither overwritten nor removed.
+        write(target_dir.join(".ssh/config"), "User local\n").unwrap();
+        assert!(matches!(run_with(options("me"), &stow_dir, &target_dir, &[(Stow, "a")]), Err(Error::Conflicts(_))));
+        run_with(options("me"), &stow_dir, &target_dir, &[(super::super::Operation::Unstow, "a")]).unwrap();
+        assert_eq!(read_to_string(target_dir.join(".ssh/config")).unwrap(), "User local\n");
+        assert!(!target_dir.join(".bashrc").exists());
+        assert!(!target_dir.join(".ssh/known_hosts").exists());
+    }
+
+    #[test]
+    fn test_stow_unfolds_own_directory_for_new_template() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/app/rc", "a/.config/app/theme"]);
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert!(target_dir.join(".config").is_symlink());
+
+        write(stow_dir.join("a/.config/app/local.tmpl"), "{{ os }}").unwrap();
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert!(!target_dir.join(".config").is_symlink());
+        assert!(!target_dir.join(".config/app").is_symlink());
+        assert_eq!(read_link(target_dir.join(".config/app/rc")).unwrap(), PathBuf::from("../../../stow/a/.config/app/rc"));
+        assert_eq!(read_to_string(target_dir.join(".config/app/local")).unwrap(), "");
+
+        run(&stow_dir, &target_dir, &[(super::super::Operation::Unstow, "a")]).unwrap();
+        assert_eq!(target_dir.read_dir().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_stow_selects_alternates() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(
+            &stow_dir,
+            &["a/.bashrc##default", "a/.bashrc##host.laptop", "a/.bashrc##os.linux", "a/.config##host.desktop/rc"],
+        );
+        let on = |hostname: &str| Options {
+            variables: Variables {
+                hostname: hostname.to_string(),
+                os: "linux".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        run_with(on("laptop"), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert_eq!(read_link(target_dir.join(".bashrc")).unwrap(), PathBuf::from("../stow/a/.bashrc##host.laptop"));
+        assert!(!target_dir.join(".config").exists());
+
+        // Moving to another machine links its alternates instead.
+        run_with(on("desktop"), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert_eq!(read_link(target_dir.join(".bashrc")).unwrap(), PathBuf::from("../stow/a/.bashrc##os.linux"));
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), PathBuf::from("../stow/a/.config##host.desktop"));
+
+        // Whichever alternate is linked is owned by the package.
+        run_with(on("laptop"), &stow_dir, &target_dir, &[(super::super::Operation::Unstow, "a")]).unwrap();
+        assert_eq!(target_dir.read_dir().unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::fs::{resolve_link, Action, Entry, EntryKind, Package, PackageImpl, PathState, Symlink};
+use crate::state::FileOrigin;
+
+use super::{Planner, SkipReason, Skipped};
+
+impl Planner<'_> {
+    pub(super) fn unstow<P: Package>(&mut self, package: &P) -> Result<(), Error> {
+        // The directories descended into, which are cleaned up once all of
+        // the entries inside them are unstowed.
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        // The target directories cleaned up already. Cleaning up may fold a
+        // directory into a link of another package, which the other
+        // alternates of the same directory must leave alone.
+        let mut cleaned: HashSet<PathBuf> = HashSet::new();
+        // Whichever alternate was selected when the package was stowed, its
+        // links are removed.
+        let mut walk = package.walk_with_alternates();
+        while let Some(entry) = walk.next() {
+            let entry = entry?;
+            while let Some(dir) = dirs.pop_if(|dir| !entry.path.starts_with(dir)) {
+                cleaned.insert(self.clean_up(package, &dir)?);
+            }
+
+            if cleaned.contains(&self.target_path_of(&package.installed_path(&entry))) {
+                walk.skip_dir();
+            } else if self.unstow_entry(package, &entry)? {
+                dirs.push(entry.path);
+            } else {
+                walk.skip_dir();
+            }
+        }
+        while let Some(dir) = dirs.pop() {
+            self.clean_up(package, &dir)?;
+        }
+        Ok(())
+    }
+
+    /// Cleans up the target directory of the package directory `dir`, and
+    /// returns it.
+    fn clean_up<P: Package>(&mut self, package: &P, dir: &Path) -> Result<PathBuf, Error> {
+        let target_dir = self.target_path_of(dir);
+        self.clean_up_dir(package, &target_dir)?;
+        Ok(target_dir)
+    }
+
+    /// Unstows `entry`, and returns true if the entries inside it have to be
+    /// unstowed one by one as well.
+    fn unstow_entry<P: Package>(&mut self, package: &P, entry: &Entry) -> Result<bool, Error> {
+        let link_path = self.target_path_of(&package.installed_path(entry));
+        let source = package.path().join(&entry.path);
+
+        match self.state_of(&link_path)? {
+            PathState::Symlink(existing_target)
+                if is_owned_by_package(package, &entry.path, &link_path, &existing_target) =>
+            {
+                self.plan(Action::Unlink(link_path));
+            }
+            PathState::Symlink(_) => self.skip(package, link_path, SkipReason::NotOwned),
+            PathState::Dir if entry.is_dir() => return Ok(true),
+            PathState::File if entry.kind == EntryKind::File => match self.state.origin_of(&link_path, &source)? {
+                FileOrigin::Hardlink | FileOrigin::Copy => self.plan(Action::RemoveFile(link_path)),
+                FileOrigin::ModifiedCopy => self.skip(package, link_path, SkipReason::Modified),
+                FileOrigin::Foreign => self.skip(package, link_path, SkipReason::Foreign),
+            },
+            PathState::Dir | PathState::File => self.skip(package, link_path, SkipReason::NotALink),
+            PathState::Missing => {}
+        }
+
+        Ok(false)
+    }
+
+    /// Records that `path` is left alone when unstowing `package`.
+    fn skip<P: Package>(&mut self, package: &P, path: PathBuf, reason: SkipReason) {
+        self.skipped.push(Skipped {
+            path,
+            package: package.name().to_string(),
+            reason,
+        });
+    }
+
+    /// Removes the directory `dir` if it was created by syra and is left
+    /// empty, or folds it back into a single directory symlink if all that is
+    /// left in it are the links of one other package.
+    pub(super) fn clean_up_dir<P: Package>(&mut self, package: &P, dir: &Path) -> Result<(), Error> {
+        if !self.is_created_dir(dir) {
+            return Ok(());
+        }
+
+        let remaining = self.entries_of(dir)?;
+        if remaining.is_empty() {
+            self.options.log.write(format_args!("plan: Removing empty directory {:?}", dir));
+            self.plan(Action::RemoveDir(dir.to_path_buf()));
+        } else if let Some(source_dir) = self.single_package_dir(package, &remaining)? {
+            self.options.log.write(format_args!("plan: Folding {:?} into a link to {:?}", dir, source_dir));
+            for path in remaining {
+                self.plan(Action::Unlink(path));
+            }
+            self.plan(Action::RemoveDir(dir.to_path_buf()));
+            let target = self.link_target(&source_dir, dir)?;
+            self.plan(Action::Link(Symlink {
+                path: dir.to_path_buf(),
+                target,
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the package directory that the links in `entries` point into,
+    /// if they are links to every entry of a single directory of a package
+    /// other than `package`.
+    fn single_package_dir<P: Package>(&self, package: &P, entries: &[PathBuf]) -> Result<Option<PathBuf>, Error> {
+        let Some(stow_dir) = package.path().parent() else {
+            return Ok(None);
+        };
+
+        let mut source_dir: Option<PathBuf> = None;
+        for path in entries {
+            let PathState::Symlink(link_target) = self.state_of(path)? else {
+                return Ok(None);
+            };
+            let resolved = resolve_link(path, &link_target);
+            if resolved.file_name().map(|name| self.target_name_of(name)).as_deref() != path.file_name().map(Path::new) {
+                return Ok(None);
+            }
+            let Some(parent) = resolved.parent() else {
+                return Ok(None);
+            };
+            match &source_dir {
+                None => source_dir = Some(parent.to_path_buf()),
+                Some(dir) if dir == parent => {}
+                Some(_) => return Ok(None),
+            }
+        }
+
+        let Some(source_dir) = source_dir else {
+            return Ok(None);
+        };
+        if source_dir.starts_with(package.path()) {
+            return Ok(None);
+        }
+        let Some((other, dir)) = PackageImpl::containing(stow_dir, &source_dir, &self.options.ignore, &self.machine())
+        else {
+            return Ok(None);
+        };
+        if dir.as_os_str().is_empty() || !source_dir.is_dir() {
+            return Ok(None);
+        }
+
+        let contents = other.read_dir(&dir)?;
+        let all_linked = contents.len() == entries.len()
+            && contents
+                .iter()
+                .all(|entry| {
+                    let name = self.target_name_of(entry.path.file_name().unwrap_or_default());
+                    entries.iter().any(|path| path.file_name() == Some(name.as_os_str()))
+                });
+
+        Ok(all_linked.then_some(source_dir))
+    }
+}
+
+/// Returns true if the symlink at `link_path`, which points to `link_target`,
+/// links to `item`, a path relative to the root of `package`, or to another
+/// alternate of it.
+pub(super) fn is_owned_by_package<P: Package>(package: &P, item: &Path, link_path: &Path, link_target: &Path) -> bool {
+    package.owns_link(item, link_path, link_target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{create_files, plan_with, run, run_with, setup};
+    use super::super::Operation::{Stow, Unstow};
+    use super::super::Options;
+    use super::*;
+    use crate::fs::{symlink, Target, TargetImpl};
+    use crate::state::State;
+    use std::fs::{create_dir_all, read_link};
+
+    struct TestPackage {
+        path: PathBuf,
+    }
+
+    impl Package for TestPackage {
+        fn read_dir(&self, _dir: &Path) -> Result<Vec<Entry>, Error> {
+            Ok(vec![Entry {
+                path: PathBuf::from("test_item"),
+                kind: crate::fs::EntryKind::File,
+            }])
+        }
+
+        fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn name(&self) -> &str {
+            "test_package"
+        }
+    }
+
+    #[test]
+    fn test_do_unstow() {
+        let package = TestPackage{path: PathBuf::from("/path/to/package")};
+
+        let (_tmp, _stow_dir, target_dir) = setup();
+        let target = TargetImpl::new(&target_dir).unwrap();
+        let state = State::load(&target_dir).unwrap();
+        let options = Options {
+            log: crate::log::Log::new(|_| {}),
+            ..Default::default()
+        };
+        let mut planner = Planner::new(&target, &state, options);
+        planner.add(Unstow, &package).expect("Unstow should succeed");
+    }
+
+    #[test]
+    fn test_links_spelled_differently_are_owned() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.profile", "a/.config/nvim/init.vim"]);
+        create_dir_all(target_dir.join(".config")).unwrap();
+        symlink(stow_dir.join("a/.bashrc"), target_dir.join(".bashrc")).unwrap();
+        symlink("../stow/./a/.profile", target_dir.join(".profile")).unwrap();
+        symlink("../../stow/a/../a/.config/nvim", target_dir.join(".config/nvim")).unwrap();
+
+        let plan = super::super::tests::plan(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert!(plan.conflicts.is_empty());
+        assert!(plan.actions.is_empty());
+
+        let package = PackageImpl::new(&stow_dir, "a").unwrap();
+        let items = TargetImpl::new(&target_dir)
+            .unwrap()
+            .get_installed_package_contents(&package, &State::load(&target_dir).unwrap())
+            .unwrap();
+        assert!(items.iter().all(|item| !matches!(item, crate::fs::InstalledItem::NotOwned(_))));
+
+        run(&stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+        assert!(!target_dir.join(".bashrc").exists());
+        assert!(!target_dir.join(".profile").exists());
+        assert!(!target_dir.join(".config/nvim").exists());
+        assert!(target_dir.join(".config").is_dir());
+    }
+
+    #[test]
+    fn test_unstow_removes_links() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/nvim/init.vim"]);
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        run(&stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+
+        assert!(!target_dir.join(".bashrc").is_symlink());
+        assert!(!target_dir.join(".config").is_symlink());
+        assert_eq!(target_dir.read_dir().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_unstow_keeps_foreign_links() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc"]);
+        crate::fs::symlink("/etc/bashrc", target_dir.join(".bashrc")).unwrap();
+
+        run(&stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+
+        assert_eq!(read_link(target_dir.join(".bashrc")).unwrap(), PathBuf::from("/etc/bashrc"));
+    }
+
+    #[test]
+    fn test_unstow_keeps_preexisting_directories() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/nvim/init.vim", "a/.local/bin/tool"]);
+        create_files(&target_dir, &[".config/user.conf"]);
+        create_dir_all(target_dir.join(".local")).unwrap();
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        run(&stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+
+        assert!(target_dir.join(".config/user.conf").is_file());
+        assert!(!target_dir.join(".config/nvim").is_symlink());
+        assert!(target_dir.join(".local").is_dir());
+        assert!(!target_dir.join(".local/bin").is_symlink());
+    }
+
+    #[test]
+    fn test_unstow_refolds_and_removes_unfolded_directories() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(
+            &stow_dir,
+            &["a/.config/shared/a.conf", "a/.config/nvim/init.vim", "b/.config/shared/b.conf"],
+        );
+
+        run(&stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        run(&stow_dir, &target_dir, &[(Stow, "b")]).unwrap();
+        assert!(!target_dir.join(".config").is_symlink());
+        assert!(!target_dir.join(".config/shared").is_symlink());
+
+        run(&stow_dir, &target_dir, &[(Unstow, "b")]).unwrap();
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), PathBuf::from("../stow/a/.config"));
+
+        run(&stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+        assert!(!target_dir.join(".config").exists());
+        assert!(!target_dir.join(State::FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_unstow_leaves_other_alternates_of_refolded_directory_alone() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config##class.x/rc", "a/.config##class.y/rc", "b/.config/other"]);
+        let options = Options {
+            variables: crate::template::Variables {
+                class: "x".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        run(&stow_dir, &target_dir, &[(Stow, "b")]).unwrap();
+        run_with(options.clone(), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        assert!(target_dir.join(".config/rc").is_symlink());
+
+        let plan = plan_with(options.clone(), &stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+        assert!(plan.skipped.is_empty());
+        run_with(options, &stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), PathBuf::from("../stow/b/.config"));
+    }
+
+    #[test]
+    fn test_unstow_removes_directory_left_empty() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.config/a.conf", "b/.config/b.conf"]);
+
+        run(&stow_dir, &target_dir, &[(Stow, "a"), (Stow, "b")]).unwrap();
+        run(&stow_dir, &target_dir, &[(Unstow, "a"), (Unstow, "b")]).unwrap();
+
+        assert!(!target_dir.join(".config").exists());
+    }
+
+    #[test]
+    fn test_unstow_dotfiles() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/dot-bashrc", "a/dot-config/dot-a.conf", "b/dot-config/b.conf"]);
+        let dotfiles = Options {
+            dotfiles: true,
+            ..Default::default()
+        };
+
+        run_with(dotfiles.clone(), &stow_dir, &target_dir, &[(Stow, "a"), (Stow, "b")]).unwrap();
+        assert!(target_dir.join(".config/.a.conf").is_symlink());
+
+        run_with(dotfiles.clone(), &stow_dir, &target_dir, &[(Unstow, "b")]).unwrap();
+        assert_eq!(read_link(target_dir.join(".config")).unwrap(), PathBuf::from("../stow/a/dot-config"));
+
+        run_with(dotfiles, &stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+        assert_eq!(target_dir.read_dir().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_unstow_keeps_modified_copies() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/a.conf", "a/.config/b.conf", "a/.local/app.conf"]);
+        let copy = Options {
+            install_mode: crate::fs::InstallMode::Copy,
+            ..Default::default()
+        };
+
+        run_with(copy.clone(), &stow_dir, &target_dir, &[(Stow, "a")]).unwrap();
+        std::fs::write(target_dir.join(".config/b.conf"), "local").unwrap();
+        let plan = plan_with(copy.clone(), &stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+        assert_eq!(
+            plan.skipped,
+            vec![Skipped {
+                path: target_dir.join(".config/b.conf"),
+                package: "a".to_string(),
+                reason: SkipReason::Modified,
+            }]
+        );
+        let actions = run_with(copy, &stow_dir, &target_dir, &[(Unstow, "a")]).unwrap();
+
+        assert_eq!(actions.len(), 4);
+        assert!(!target_dir.join(".bashrc").exists());
+        assert!(!target_dir.join(".local").exists());
+        assert!(!target_dir.join(".config/a.conf").exists());
+        assert_eq!(std::fs::read_to_string(target_dir.join(".config/b.conf")).unwrap(), "local");
+    }
+}
@@ -0,0 +1,430 @@
+//! Machine-readable reports, printed with `--output json` and returned by
+//! `Syra`.
+//!
+//! Every command prints a single JSON document to stdout. Its `version` is
+//! increased whenever a field is removed or changes meaning, so scripts can
+//! tell when they need updating. Paths are plain strings; paths that are not
+//! valid UTF-8 are converted lossily.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::doctor::Problem;
+use crate::error::{Error, IoAction, IoContext};
+use crate::fs::{Action, InstalledItem, Symlink};
+use crate::plan::{Operation, SkipReason, Skipped};
+use crate::status::PackageStatus;
+
+/// The version of the documents described in this module.
+pub const VERSION: u32 = 1;
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Prints `report` as a JSON document on stdout.
+pub fn print<T: Serialize>(report: &T) -> Result<(), Error> {
+    let stdout = Path::new("<stdout>");
+    let json = serde_json::to_string_pretty(report)
+        .map_err(|e| Error::io(IoAction::Write, stdout, e.into()))?;
+    writeln!(io::stdout().lock(), "{}", json).at(IoAction::Write, stdout)?;
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ActionReport {
+    CreateDir { path: String },
+    Link { path: String, target: String },
+    Unlink { path: String },
+    RemoveDir { path: String },
+    Unfold { path: String },
+    Adopt { path: String, target: String },
+    Rename { from: String, to: String },
+    Copy { path: String, source: String },
+    Hardlink { path: String, source: String },
+    RemoveFile { path: String },
+    Render { path: String, source: String },
+}
+
+impl From<&Action> for ActionReport {
+    fn from(action: &Action) -> Self {
+        match action {
+            Action::CreateDir(path) => ActionReport::CreateDir { path: path_string(path) },
+            Action::Link(Symlink { path, target }) => ActionReport::Link {
+                path: path_string(path),
+                target: path_string(target),
+            },
+            Action::Unlink(path) => ActionReport::Unlink { path: path_string(path) },
+            Action::RemoveDir(path) => ActionReport::RemoveDir { path: path_string(path) },
+            Action::Unfold(path) => ActionReport::Unfold { path: path_string(path) },
+            Action::Adopt(Symlink { path, target }) => ActionReport::Adopt {
+                path: path_string(path),
+                target: path_string(target),
+            },
+            Action::Rename { from, to } => ActionReport::Rename {
+                from: path_string(from),
+                to: path_string(to),
+            },
+            Action::Copy { source, path } => ActionReport::Copy {
+                path: path_string(path),
+                source: path_string(source),
+            },
+            Action::Hardlink { source, path } => ActionReport::Hardlink {
+                path: path_string(path),
+                source: path_string(source),
+            },
+            Action::RemoveFile(path) => ActionReport::RemoveFile { path: path_string(path) },
+            Action::Render { source, path, .. } => ActionReport::Render {
+                path: path_string(path),
+                source: path_string(source),
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ConflictReport {
+    /// `link_path_exists`, `link_not_owned_by_package`, `package_conflict`,
+    /// `package_needed`, or `other`.
+    pub kind: &'static str,
+    pub path: Option<String>,
+    pub package: Option<String>,
+    pub message: String,
+}
+
+impl From<&Error> for ConflictReport {
+    fn from(error: &Error) -> Self {
+        let (kind, path, package) = match error {
+            Error::LinkPathExists(path, package) => ("link_path_exists", Some(path), Some(package)),
+            Error::LinkNotOwnedByPackage(path, package) => ("link_not_owned_by_package", Some(path), Some(package)),
+            Error::PackageConflict { package, .. } => ("package_conflict", None, Some(package)),
+            Error::PackageNeeded { package, .. } => ("package_needed", None, Some(package)),
+            _ => ("other", None, None),
+        };
+        Self {
+            kind,
+            path: path.map(|path| path_string(path)),
+            package: package.cloned(),
+            message: error.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SkippedReport {
+    /// `not_owned`, `modified`, `foreign` or `not_a_link`.
+    pub reason: &'static str,
+    pub path: String,
+    pub package: String,
+    pub message: String,
+}
+
+impl From<&Skipped> for SkippedReport {
+    fn from(skipped: &Skipped) -> Self {
+        let reason = match skipped.reason {
+            SkipReason::NotOwned => "not_owned",
+            SkipReason::Modified => "modified",
+            SkipReason::Foreign => "foreign",
+            SkipReason::NotALink => "not_a_link",
+        };
+        Self {
+            reason,
+            path: path_string(&skipped.path),
+            package: skipped.package.clone(),
+            message: skipped.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The plan was executed.
+    Success,
+    /// The plan was only printed, because of `-n`.
+    Simulated,
+    /// The plan had conflicts and nothing was executed.
+    Conflicts,
+    /// An action failed and the ones executed before it were undone.
+    RolledBack,
+    /// The run failed for another reason.
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedAction {
+    pub action: ActionReport,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub outcome: Outcome,
+    pub error: Option<String>,
+    pub planned: usize,
+    pub conflicts: usize,
+    pub executed: usize,
+    pub rolled_back: usize,
+}
+
+/// The report of a stow, unstow or restow run.
+#[derive(Debug, Serialize)]
+pub struct OperationReport {
+    pub version: u32,
+    pub command: &'static str,
+    pub simulate: bool,
+    pub packages: Vec<String>,
+    pub plan: Vec<ActionReport>,
+    pub conflicts: Vec<ConflictReport>,
+    /// The paths that unstowing left alone.
+    pub skipped: Vec<SkippedReport>,
+    /// The actions that were executed and left in place.
+    pub executed: Vec<ActionReport>,
+    /// The action that failed, if any. A failed hook has no action, its
+    /// error is in the summary.
+    pub failed: Option<FailedAction>,
+    /// The actions executed to undo the changes made before a failure.
+    pub rolled_back: Vec<ActionReport>,
+    pub summary: Summary,
+}
+
+impl OperationReport {
+    pub fn new(operation: Operation, packages: &[String], simulate: bool) -> Self {
+        let command = match operation {
+            Operation::Stow => "stow",
+            Operation::Unstow => "unstow",
+            Operation::Restow => "restow",
+        };
+        Self {
+            version: VERSION,
+            command,
+            simulate,
+            packages: packages.to_vec(),
+            plan: Vec::new(),
+            conflicts: Vec::new(),
+            skipped: Vec::new(),
+            executed: Vec::new(),
+            failed: None,
+            rolled_back: Vec::new(),
+            summary: Summary {
+                outcome: Outcome::Success,
+                error: None,
+                planned: 0,
+                conflicts: 0,
+                executed: 0,
+                rolled_back: 0,
+            },
+        }
+    }
+
+    /// Fills in the summary from the result of the run.
+    pub fn finish<T>(&mut self, result: &Result<T, Error>) {
+        let outcome = match result {
+            Ok(_) if self.simulate => Outcome::Simulated,
+            Ok(_) => Outcome::Success,
+            Err(Error::Conflicts(_)) => Outcome::Conflicts,
+            Err(Error::RolledBack(rollback)) => {
+                self.failed = rollback.failed.as_ref().map(|failed| FailedAction {
+                    action: failed.into(),
+                    error: rollback.error.to_string(),
+                });
+                self.rolled_back = rollback.undone.iter().map(ActionReport::from).collect();
+                Outcome::RolledBack
+            }
+            Err(_) => Outcome::Error,
+        };
+        self.summary = Summary {
+            outcome,
+            error: result.as_ref().err().map(Error::to_string),
+            planned: self.plan.len(),
+            conflicts: self.conflicts.len(),
+            executed: self.executed.len(),
+            rolled_back: self.rolled_back.len(),
+        };
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ItemReport {
+    Owned { path: String, target: String },
+    Foreign { path: String, target: String },
+    /// A copy of the package entry, or a hard link to it.
+    Installed { path: String },
+    /// A copy of the package entry that was modified after it was installed.
+    Modified { path: String },
+    Dir { path: String },
+    File { path: String },
+    Missing { path: String },
+}
+
+impl From<&InstalledItem> for ItemReport {
+    fn from(item: &InstalledItem) -> Self {
+        match item {
+            InstalledItem::Item(Symlink { path, target }) => ItemReport::Owned {
+                path: path_string(path),
+                target: path_string(target),
+            },
+            InstalledItem::NotOwned(Symlink { path, target }) => ItemReport::Foreign {
+                path: path_string(path),
+                target: path_string(target),
+            },
+            InstalledItem::Installed(path) => ItemReport::Installed { path: path_string(path) },
+            InstalledItem::Modified(path) => ItemReport::Modified { path: path_string(path) },
+            InstalledItem::Dir(path) => ItemReport::Dir { path: path_string(path) },
+            InstalledItem::File(path) => ItemReport::File { path: path_string(path) },
+            InstalledItem::Missing(path) => ItemReport::Missing { path: path_string(path) },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackageReport {
+    pub name: String,
+    pub status: PackageStatus,
+    /// Only filled in by `status`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Vec<ItemReport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflicts: Option<Vec<ConflictReport>>,
+    /// The selected alternates, only filled in by `status`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alternates: Option<Vec<AlternateReport>>,
+    /// Why the package is unreadable, only filled in by `list`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A package entry that was selected among its alternates.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AlternateReport {
+    /// The entry, relative to the package root.
+    pub entry: String,
+    /// The path it is installed at, relative to the target.
+    pub path: String,
+}
+
+/// The report of a status or list run.
+#[derive(Debug, Serialize)]
+pub struct PackagesReport {
+    pub version: u32,
+    pub command: &'static str,
+    pub packages: Vec<PackageReport>,
+    /// Why the command failed, if it did. The packages are the ones
+    /// reported before the failure.
+    pub error: Option<String>,
+}
+
+impl PackagesReport {
+    pub fn new(command: &'static str) -> Self {
+        Self {
+            version: VERSION,
+            command,
+            packages: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProblemReport {
+    pub problem: Problem,
+    pub path: String,
+    pub target: String,
+    /// True if `--fix` removes the link.
+    pub fixable: bool,
+}
+
+/// The report of a doctor run.
+#[derive(Debug, Serialize)]
+pub struct DoctorReport {
+    pub version: u32,
+    pub command: &'static str,
+    pub simulate: bool,
+    pub problems: Vec<ProblemReport>,
+    /// The actions taken by `--fix`, or that would be taken with `-n`.
+    pub fixes: Vec<ActionReport>,
+    /// Why the command failed, if it did.
+    pub error: Option<String>,
+}
+
+impl DoctorReport {
+    pub fn new(simulate: bool) -> Self {
+        Self {
+            version: VERSION,
+            command: "doctor",
+            simulate,
+            problems: Vec::new(),
+            fixes: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::Rollback;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_action_report() {
+        let action = Action::Link(Symlink {
+            path: PathBuf::from("/target/.bashrc"),
+            target: PathBuf::from("../stow/a/.bashrc"),
+        });
+        let json = serde_json::to_value(ActionReport::from(&action)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"action": "link", "path": "/target/.bashrc", "target": "../stow/a/.bashrc"})
+        );
+
+        let json = serde_json::to_value(ConflictReport::from(&Error::LinkPathExists(
+            PathBuf::from("/target/.vimrc"),
+            "b".to_string(),
+        )))
+        .unwrap();
+        assert_eq!(json["kind"], "link_path_exists");
+        assert_eq!(json["path"], "/target/.vimrc");
+        assert_eq!(json["package"], "b");
+    }
+
+    #[test]
+    fn test_operation_report_summary() {
+        let mut report = OperationReport::new(Operation::Stow, &["a".to_string()], false);
+        report.plan = vec![
+            ActionReport::CreateDir { path: "/target/.config".to_string() },
+            ActionReport::Link {
+                path: "/target/.config/nvim".to_string(),
+                target: "../../stow/a/.config/nvim".to_string(),
+            },
+        ];
+        report.finish::<()>(&Err(Error::RolledBack(Box::new(Rollback {
+            failed: Some(Action::Link(Symlink {
+                path: PathBuf::from("/target/.config/nvim"),
+                target: PathBuf::from("../../stow/a/.config/nvim"),
+            })),
+            error: Error::io(
+                IoAction::Symlink,
+                Path::new("/target/.config/nvim"),
+                io::Error::from(io::ErrorKind::PermissionDenied),
+            ),
+            undone: vec![Action::RemoveDir(PathBuf::from("/target/.config"))],
+            undo_failures: Vec::new(),
+        }))));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["version"], VERSION);
+        assert_eq!(json["command"], "stow");
+        assert_eq!(json["failed"]["action"]["action"], "link");
+        assert_eq!(json["failed"]["error"], "symlink '/target/.config/nvim' failed: permission denied");
+        assert_eq!(json["rolled_back"][0]["action"], "remove_dir");
+        assert_eq!(json["summary"]["outcome"], "rolled_back");
+        assert_eq!(json["summary"]["planned"], 2);
+        assert_eq!(json["summary"]["executed"], 0);
+        assert_eq!(json["summary"]["rolled_back"], 1);
+    }
+}
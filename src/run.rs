@@ -0,0 +1,609 @@
+//! Runs of stow, unstow and restow operations, shared by the command line and
+//! the library.
+
+use std::collections::HashSet;
+use std::env::current_dir;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::alternate::Machine;
+use crate::error::{Error, IoAction, IoContext};
+use crate::exec::Executor;
+use crate::exit;
+use crate::fs::{Action, InstallMode, InstalledItem, LinkMode, Package, PackageImpl, Target, TargetImpl};
+use crate::hook::{self, Hook};
+use crate::log::Log;
+use crate::manifest;
+use crate::plan::{Operation, Options, Planner};
+use crate::report::{ActionReport, ConflictReport, OperationReport, SkippedReport};
+use crate::state::State;
+use crate::template::Variables;
+
+/// How a command that did not fail ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Completion {
+    /// Everything was done, or there was nothing to do.
+    Done,
+    /// The command was run with `-n`, and there are changes to make.
+    ChangesPending,
+}
+
+impl Completion {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Completion::Done => exit::SUCCESS,
+            Completion::ChangesPending => exit::CHANGES_PENDING,
+        }
+    }
+}
+
+/// A run that failed, with the report of what it planned and did before it
+/// failed.
+pub struct Failure {
+    pub error: Error,
+    pub report: Box<OperationReport>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl fmt::Debug for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for Failure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<Failure> for Error {
+    fn from(failure: Failure) -> Self {
+        failure.error
+    }
+}
+
+/// Stows, unstows and restows packages, like the `syra` command does.
+///
+/// Nothing is printed; the outcome of a run is returned as a report, which
+/// a failed run returns along with its error. Unlike the command,
+/// configuration files are not read.
+///
+/// ```no_run
+/// let report = syra::Syra::new()
+///     .stow_dir("/home/me/dotfiles")
+///     .target("/home/me")
+///     .stow(["bash"])?;
+/// for action in &report.executed {
+///     println!("{:?}", action);
+/// }
+/// # Ok::<(), syra::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Syra {
+    pub(crate) stow_dir: Option<PathBuf>,
+    pub(crate) target_dir: Option<PathBuf>,
+    pub(crate) options: Options,
+    pub(crate) simulate: bool,
+    pub(crate) force: bool,
+    /// Prints conflicts and the actions of simulated runs, for the command line.
+    pub(crate) print: bool,
+}
+
+impl Default for Syra {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Syra {
+    pub fn new() -> Self {
+        let options = Options {
+            variables: Variables::new::<&str>(&[]).unwrap_or_default(),
+            ..Default::default()
+        };
+        Self {
+            stow_dir: None,
+            target_dir: None,
+            options,
+            simulate: false,
+            force: false,
+            print: false,
+        }
+    }
+
+    /// Sets the directory containing the packages. It defaults to the
+    /// current directory.
+    pub fn stow_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stow_dir = Some(path.into());
+        self
+    }
+
+    /// Sets the directory that packages are installed into. It defaults to
+    /// the parent of the stow directory.
+    pub fn target(mut self, path: impl Into<PathBuf>) -> Self {
+        self.target_dir = Some(path.into());
+        self
+    }
+
+    /// Only plans runs, as with `-n`.
+    pub fn simulate(mut self, simulate: bool) -> Self {
+        self.simulate = simulate;
+        self
+    }
+
+    /// Passes messages about what runs do, which `-v` prints, to `sink`.
+    pub fn log(mut self, sink: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.options.log = Log::new(sink);
+        self
+    }
+
+    /// Adopts files that are in the way of links, as with `--adopt`.
+    pub fn adopt(mut self, adopt: bool) -> Self {
+        self.options.adopt = adopt;
+        self
+    }
+
+    /// Installs package entries named `dot-*` as `.*`, as with `--dotfiles`.
+    pub fn dotfiles(mut self, dotfiles: bool) -> Self {
+        self.options.dotfiles = dotfiles;
+        self
+    }
+
+    /// Unstows packages that stowed packages depend on, as with `--force`.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn link_mode(mut self, link_mode: LinkMode) -> Self {
+        self.options.link_mode = link_mode;
+        self
+    }
+
+    pub fn install_mode(mut self, install_mode: InstallMode) -> Self {
+        self.options.install_mode = install_mode;
+        self
+    }
+
+    /// Ignores package entries matching `patterns`, as with `--ignore`.
+    pub fn ignore<I, S>(mut self, patterns: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for pattern in patterns {
+            self.options.ignore.add(pattern.as_ref())?;
+        }
+        Ok(self)
+    }
+
+    /// Sets a variable for templates, as with `--var`.
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.variables.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Sets the class that alternates are selected for, as with `--class`.
+    pub fn class(mut self, class: impl Into<String>) -> Self {
+        self.options.variables.class = class.into();
+        self
+    }
+
+    pub fn stow<I, S>(&self, packages: I) -> Result<OperationReport, Failure>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.run(Operation::Stow, packages)
+    }
+
+    pub fn unstow<I, S>(&self, packages: I) -> Result<OperationReport, Failure>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.run(Operation::Unstow, packages)
+    }
+
+    pub fn restow<I, S>(&self, packages: I) -> Result<OperationReport, Failure>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.run(Operation::Restow, packages)
+    }
+
+    /// Applies `operation` to `packages`. Runs that fail change nothing,
+    /// except when a hook fails, which leaves the packages before it changed.
+    pub fn run<I, S>(&self, operation: Operation, packages: I) -> Result<OperationReport, Failure>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let packages: Vec<String> = packages.into_iter().map(Into::into).collect();
+        let mut report = OperationReport::new(operation, &packages, self.simulate);
+        let result = self.execute(operation, &packages, &mut report);
+        report.finish(&result);
+        match result {
+            Ok(_) => Ok(report),
+            Err(error) => Err(Failure {
+                error,
+                report: Box::new(report),
+            }),
+        }
+    }
+
+    /// Plans `operation` for `packages` and, unless the plan has conflicts,
+    /// executes it, filling in `report` along the way.
+    pub(crate) fn execute(
+        &self,
+        operation: Operation,
+        packages: &[String],
+        report: &mut OperationReport,
+    ) -> Result<Completion, Error> {
+        if packages.is_empty() {
+            return Err(Error::MissingPackages);
+        }
+        let cwd = current_dir().at(IoAction::CurrentDir, Path::new("."))?;
+        let (stow_dir, target_dir) = resolve_dirs(self.stow_dir.as_deref(), self.target_dir.as_deref(), &cwd)?;
+        let target = TargetImpl::new(&target_dir)?.with_dotfiles(self.options.dotfiles);
+        let mut state = State::load(target.path())?;
+
+        let packages = match operation {
+            Operation::Stow | Operation::Restow => self.packages_with_dependencies(&stow_dir, packages)?,
+            Operation::Unstow => packages
+                .iter()
+                .map(|name| load_package(&stow_dir, name, &self.options))
+                .collect::<Result<_, _>>()?,
+        };
+        report.packages = packages.iter().map(|package| package.name().to_string()).collect();
+        let mut conflicts = self.package_conflicts(&stow_dir, &target, operation, &packages, &state)?;
+
+        let mut planner = Planner::new(&target, &state, self.options.clone());
+        for package in &packages {
+            planner.add(operation, package)?;
+        }
+        let mut plan = planner.finish();
+        conflicts.append(&mut plan.conflicts);
+        plan.conflicts = conflicts;
+        report.plan = plan.actions.iter().map(ActionReport::from).collect();
+        report.conflicts = plan.conflicts.iter().map(ConflictReport::from).collect();
+        report.skipped = plan.skipped.iter().map(SkippedReport::from).collect();
+        if self.print {
+            for skipped in &plan.skipped {
+                eprintln!("{}", skipped);
+            }
+        }
+
+        if !plan.conflicts.is_empty() {
+            if self.print {
+                for conflict in &plan.conflicts {
+                    eprintln!("conflict: {}", conflict);
+                }
+            }
+            return Err(Error::Conflicts(plan.conflicts));
+        }
+
+        if self.simulate {
+            if self.print {
+                for action in &plan.actions {
+                    println!("{}", action);
+                }
+            }
+            return Ok(if plan.actions.is_empty() {
+                Completion::Done
+            } else {
+                Completion::ChangesPending
+            });
+        }
+
+        // Each package is executed between its hooks. A failed action rolls
+        // back the whole run, a failed post hook only its own package.
+        let runner = hook::Runner {
+            target_dir: target.path(),
+            log: self.options.log.clone(),
+            json: !self.print,
+        };
+        let (pre, post) = Hook::around(operation);
+        let mut executor = Executor::new(&mut state, self.options.log.clone());
+        let mut result = Ok(());
+        for (index, package) in packages.iter().enumerate() {
+            let indices = plan.actions_of(index);
+            if indices.is_empty() {
+                continue;
+            }
+            let actions: Vec<Action> = indices.iter().map(|&index| plan.actions[index].clone()).collect();
+            let changed: Vec<&Path> = actions.iter().map(Action::path).collect();
+
+            if let Err(e) = runner.run(pre, package, &changed) {
+                result = Err(e);
+                break;
+            }
+            let mark = executor.mark();
+            if let Err(e) = executor.run(&actions).map_err(|e| e.in_package(package.name())) {
+                report.executed.clear();
+                result = Err(e);
+                break;
+            }
+            if let Err(e) = runner.run(post, package, &changed) {
+                result = Err(executor.roll_back_to(mark, None, e));
+                break;
+            }
+            report.executed.extend(indices.iter().map(|&index| report.plan[index].clone()));
+        }
+        let finished = executor.finish();
+        state.save()?;
+        result.and(finished).map(|()| Completion::Done)
+    }
+
+    /// Returns the packages named `names` together with the packages they
+    /// depend on, each after its dependencies.
+    fn packages_with_dependencies(&self, stow_dir: &Path, names: &[String]) -> Result<Vec<PackageImpl>, Error> {
+        manifest::resolve(names, |name| {
+            let package = load_package(stow_dir, name, &self.options)?;
+            let manifest = package.manifest().clone();
+            Ok((package, manifest))
+        })
+    }
+
+    /// Returns the conflicts that the manifests of `packages` and of the
+    /// other packages in the stow directory declare for `operation`.
+    ///
+    /// Stowing a package conflicts with the packages it declares conflicts
+    /// with, if they are stowed or stowed along with it, and with the stowed
+    /// packages that declare conflicts with it. Unstowing a package conflicts
+    /// with the stowed packages that depend on it, unless they are unstowed
+    /// along with it or `force` is set.
+    fn package_conflicts(
+        &self,
+        stow_dir: &Path,
+        target: &TargetImpl,
+        operation: Operation,
+        packages: &[PackageImpl],
+        state: &State,
+    ) -> Result<Vec<Error>, Error> {
+        let names: HashSet<&str> = packages.iter().map(|package| package.name()).collect();
+        let mut conflicts = Vec::new();
+
+        if operation != Operation::Unstow {
+            for package in packages {
+                for other in &package.manifest().conflicts {
+                    if names.contains(other.as_str())
+                        || self.load_other(stow_dir, other).is_some_and(|other| self.is_other_stowed(target, &other, state))
+                    {
+                        conflicts.push(Error::PackageConflict {
+                            package: package.name().to_string(),
+                            other: other.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        if operation == Operation::Unstow && self.force {
+            return Ok(conflicts);
+        }
+
+        for name in package_names(stow_dir)? {
+            if names.contains(name.as_str()) {
+                continue;
+            }
+            let Some(other) = self.load_other(stow_dir, &name) else {
+                continue;
+            };
+            let manifest = other.manifest();
+            let related: Vec<&str> = match operation {
+                Operation::Unstow => &manifest.depends,
+                Operation::Stow | Operation::Restow => &manifest.conflicts,
+            }
+            .iter()
+            .map(String::as_str)
+            .filter(|related| names.contains(related))
+            .collect();
+            if related.is_empty() || !self.is_other_stowed(target, &other, state) {
+                continue;
+            }
+            for package in related {
+                conflicts.push(match operation {
+                    Operation::Unstow => Error::PackageNeeded {
+                        package: package.to_string(),
+                        by: name.clone(),
+                    },
+                    Operation::Stow | Operation::Restow => Error::PackageConflict {
+                        package: name.clone(),
+                        other: package.to_string(),
+                    },
+                });
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    /// Returns the package `name`, which was neither named nor is a
+    /// dependency, or `None` if it does not exist or cannot be read. Such a
+    /// package must not fail the run.
+    fn load_other(&self, stow_dir: &Path, name: &str) -> Option<PackageImpl> {
+        match load_package(stow_dir, name, &self.options) {
+            Ok(package) => Some(package),
+            Err(Error::PackageNotFound { .. }) => None,
+            Err(e) => {
+                if self.print {
+                    eprintln!("warning: ignoring package '{}': {}", name, e);
+                }
+                None
+            }
+        }
+    }
+
+    /// Returns true if `package`, loaded with `load_other`, is stowed. A
+    /// package whose entries cannot be read is taken as not stowed.
+    fn is_other_stowed(&self, target: &TargetImpl, package: &PackageImpl, state: &State) -> bool {
+        is_stowed(target, package, state).unwrap_or_else(|e| {
+            if self.print {
+                eprintln!("warning: ignoring package '{}': {}", package.name(), e);
+            }
+            false
+        })
+    }
+}
+
+/// Returns the package `name` in `stow_dir`, with `options` applied.
+pub(crate) fn load_package(stow_dir: &Path, name: &str, options: &Options) -> Result<PackageImpl, Error> {
+    Ok(PackageImpl::new(stow_dir, name)?
+        .ignoring(&options.ignore)
+        .selecting(Machine::of(&options.variables)))
+}
+
+/// Returns true if any item of `package` is installed in `target`.
+fn is_stowed(target: &TargetImpl, package: &PackageImpl, state: &State) -> Result<bool, Error> {
+    let items = target.get_installed_package_contents(package, state)?;
+    Ok(items.iter().any(InstalledItem::is_owned))
+}
+
+/// Returns the names of the packages in the stow directory, which are all of
+/// its directories that are not hidden.
+pub(crate) fn package_names(stow_dir: &Path) -> Result<Vec<String>, Error> {
+    let mut names = Vec::new();
+    for entry in stow_dir.read_dir().at(IoAction::ReadDir, stow_dir)? {
+        let entry = entry.at(IoAction::ReadDir, stow_dir)?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if !name.starts_with('.') && entry.path().is_dir() {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Returns the stow directory and the target directory, both canonical.
+///
+/// The stow directory defaults to `cwd`. An explicit target directory is used
+/// as given; without one, the target is the parent of the stow directory.
+pub(crate) fn resolve_dirs(
+    package_dir: Option<&Path>,
+    target_dir: Option<&Path>,
+    cwd: &Path,
+) -> Result<(PathBuf, PathBuf), Error> {
+    let package_dir = cwd.join(package_dir.unwrap_or(cwd));
+    let package_dir = package_dir.canonicalize().at(IoAction::Canonicalize, &package_dir)?;
+
+    let target_dir = match target_dir {
+        Some(target_dir) => cwd.join(target_dir),
+        None => package_dir
+            .parent()
+            .ok_or(Error::DefaultTargetNotAvailable)?
+            .to_path_buf(),
+    };
+    let target_dir = target_dir.canonicalize().at(IoAction::Canonicalize, &target_dir)?;
+
+    Ok((package_dir, target_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::tests::{create_files, setup};
+    use crate::report::Outcome;
+    use std::fs::create_dir_all;
+
+    #[test]
+    fn test_resolve_dirs() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        let root = stow_dir.parent().unwrap();
+        let home = root.join("home");
+        create_dir_all(&home).unwrap();
+
+        // Without -d and -t, the cwd is the stow directory and its parent the target.
+        assert_eq!(resolve_dirs(None, None, &stow_dir).unwrap(), (stow_dir.clone(), root.to_path_buf()));
+
+        // With -d only, the target is the parent of the stow directory, not of the cwd.
+        assert_eq!(
+            resolve_dirs(Some(&stow_dir), None, &home).unwrap(),
+            (stow_dir.clone(), root.to_path_buf())
+        );
+        assert_eq!(
+            resolve_dirs(Some(Path::new("../stow")), None, &home).unwrap(),
+            (stow_dir.clone(), root.to_path_buf())
+        );
+
+        // With -t only, the target is used as given.
+        assert_eq!(
+            resolve_dirs(None, Some(&target_dir), &stow_dir).unwrap(),
+            (stow_dir.clone(), target_dir.clone())
+        );
+        assert_eq!(
+            resolve_dirs(None, Some(Path::new("../home/")), &stow_dir).unwrap(),
+            (stow_dir.clone(), home.clone())
+        );
+
+        // With both, both are used as given.
+        assert_eq!(
+            resolve_dirs(Some(&stow_dir), Some(&home), &target_dir).unwrap(),
+            (stow_dir.clone(), home.clone())
+        );
+        assert_eq!(
+            resolve_dirs(Some(Path::new("../stow")), Some(Path::new(".")), &target_dir).unwrap(),
+            (stow_dir.clone(), target_dir.clone())
+        );
+    }
+
+    #[test]
+    fn test_resolve_dirs_without_parent() {
+        assert!(matches!(
+            resolve_dirs(Some(Path::new("/")), None, Path::new("/")),
+            Err(Error::DefaultTargetNotAvailable)
+        ));
+        assert_eq!(
+            resolve_dirs(Some(Path::new("/")), Some(Path::new("/")), Path::new("/")).unwrap(),
+            (PathBuf::from("/"), PathBuf::from("/"))
+        );
+    }
+
+    #[test]
+    fn test_package_names() {
+        let (_tmp, stow_dir, _target_dir) = setup();
+        create_files(&stow_dir, &["b/.vimrc", "a/.bashrc", ".git/HEAD", "syra.toml"]);
+
+        assert_eq!(package_names(&stow_dir).unwrap(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_syra_builder() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["bash/.bashrc", "bash/README.md", "vim/.vimrc"]);
+        let syra = Syra::new().stow_dir(&stow_dir).target(&target_dir);
+
+        let report = syra.clone().simulate(true).stow(["bash"]).unwrap();
+        assert_eq!(report.plan.len(), 1);
+        assert!(report.executed.is_empty());
+        assert!(!target_dir.join(".bashrc").exists());
+
+        let report = syra.stow(["bash", "vim"]).unwrap();
+        assert_eq!(report.executed.len(), 2);
+        assert!(target_dir.join(".bashrc").is_symlink());
+        assert!(!target_dir.join("README.md").exists());
+
+        let report = syra.unstow(["vim"]).unwrap();
+        assert_eq!(report.executed.len(), 1);
+        assert!(!target_dir.join(".vimrc").exists());
+        assert!(matches!(syra.stow(Vec::<String>::new()), Err(Failure { error: Error::MissingPackages, .. })));
+
+        let failure = syra.stow(["bash", "missing"]).unwrap_err();
+        assert!(matches!(failure.error, Error::PackageNotFound { .. }));
+        assert_eq!(failure.report.summary.outcome, Outcome::Error);
+        assert!(target_dir.join(".bashrc").is_symlink());
+
+        std::fs::write(target_dir.join(".vimrc"), "local").unwrap();
+        let failure = syra.stow(["vim"]).unwrap_err();
+        assert!(matches!(failure.error, Error::Conflicts(_)));
+        assert_eq!(failure.report.conflicts.len(), 1);
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{read_to_string, remove_file, symlink_metadata, write};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::alternate::base_path;
+use crate::error::{Error, IoAction, IoContext};
+use crate::fs::{content_hash, same_file, Action};
+
+/// Book-keeping that syra keeps about a target directory, stored in a file
+/// at the root of the target.
+///
+/// The file has one entry per line, `<kind> <path>`, where the path is
+/// relative to the target directory. The kinds are:
+/// - `dir <path>`, a directory that syra created while unfolding a directory
+///   symlink or installing files into it.
+/// - `copy <hash> <path>\t<source>`, a file copied or rendered from the
+///   package entry `source`, with the SHA-256 hash of the content it was
+///   installed with.
+pub struct State {
+    target_dir: PathBuf,
+    created_dirs: BTreeSet<PathBuf>,
+    copies: BTreeMap<PathBuf, CopyRecord>,
+}
+
+/// A file that syra copied or rendered into the target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CopyRecord {
+    pub hash: String,
+    pub source: PathBuf,
+}
+
+/// Where a file in the target comes from, as far as syra can tell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileOrigin {
+    /// A hard link to the package entry.
+    Hardlink,
+    /// A copy of the package entry, or the file rendered from it, unmodified
+    /// since it was installed.
+    Copy,
+    /// A copy or rendered file that was modified after it was installed.
+    ModifiedCopy,
+    /// Not installed from the package entry.
+    Foreign,
+}
+
+impl State {
+    pub const FILE_NAME: &'static str = ".syra-state";
+
+    /// Reads the state of `target_dir`. A missing state file is an empty state.
+    pub fn load(target_dir: &Path) -> Result<Self, Error> {
+        let mut state = Self {
+            target_dir: target_dir.to_path_buf(),
+            created_dirs: BTreeSet::new(),
+            copies: BTreeMap::new(),
+        };
+
+        let path = target_dir.join(Self::FILE_NAME);
+        let contents = match read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(state),
+            Err(e) => return Err(Error::io(IoAction::Read, &path, e)),
+        };
+        for line in contents.lines() {
+            if let Some(path) = line.strip_prefix("dir ") {
+                state.created_dirs.insert(PathBuf::from(path));
+            } else if let Some(copy) = line.strip_prefix("copy ")
+                && let Some((hash, paths)) = copy.split_once(' ')
+                && let Some((path, source)) = paths.split_once('\t')
+            {
+                let copy = CopyRecord {
+                    hash: hash.to_string(),
+                    source: PathBuf::from(source),
+                };
+                state.copies.insert(PathBuf::from(path), copy);
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Writes the state back to the target, removing the file when the state
+    /// is empty. Copies that no longer exist are forgotten.
+    pub fn save(&mut self) -> Result<(), Error> {
+        let target_dir = &self.target_dir;
+        self.copies.retain(|path, _| symlink_metadata(target_dir.join(path)).is_ok());
+
+        let path = self.target_dir.join(Self::FILE_NAME);
+        if self.created_dirs.is_empty() && self.copies.is_empty() {
+            return match remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::io(IoAction::Unlink, &path, e)),
+                _ => Ok(()),
+            };
+        }
+
+        let mut contents = String::new();
+        for dir in &self.created_dirs {
+            contents.push_str(&format!("dir {}\n", dir.display()));
+        }
+        for (file, copy) in &self.copies {
+            contents.push_str(&format!("copy {} {}\t{}\n", copy.hash, file.display(), copy.source.display()));
+        }
+        write(&path, contents).at(IoAction::Write, &path)?;
+
+        Ok(())
+    }
+
+    pub fn target_dir(&self) -> &Path {
+        &self.target_dir
+    }
+
+    /// Returns true if the directory at `path` was created by syra.
+    pub fn is_created_dir(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.target_dir)
+            .is_ok_and(|relative| self.created_dirs.contains(relative))
+    }
+
+    /// Returns the directories that syra created in the target.
+    pub fn created_dirs(&self) -> impl Iterator<Item = PathBuf> {
+        self.created_dirs.iter().map(|dir| self.target_dir.join(dir))
+    }
+
+    /// Returns the files that syra copied or rendered into the target, by
+    /// their path.
+    pub fn copies(&self) -> impl Iterator<Item = (PathBuf, &CopyRecord)> {
+        self.copies.iter().map(|(path, copy)| (self.target_dir.join(path), copy))
+    }
+
+    /// Returns true if the file at `path` was copied or rendered by syra.
+    pub fn is_copy(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.target_dir)
+            .is_ok_and(|relative| self.copies.contains_key(relative))
+    }
+
+    /// Returns where the file at `path` comes from, given that it would be
+    /// installed from `source`. Copies of other alternates of `source` count
+    /// as copies of `source`.
+    pub fn origin_of(&self, path: &Path, source: &Path) -> Result<FileOrigin, Error> {
+        if same_file(path, source)? {
+            return Ok(FileOrigin::Hardlink);
+        }
+
+        let copy = path
+            .strip_prefix(&self.target_dir)
+            .ok()
+            .and_then(|relative| self.copies.get(relative));
+        match copy {
+            Some(copy) if base_path(&copy.source) == base_path(source) => {
+                if content_hash(path)? == copy.hash {
+                    Ok(FileOrigin::Copy)
+                } else {
+                    Ok(FileOrigin::ModifiedCopy)
+                }
+            }
+            _ => Ok(FileOrigin::Foreign),
+        }
+    }
+
+    /// Updates the state after `action` has been executed.
+    pub fn record(&mut self, action: &Action) -> Result<(), Error> {
+        match action {
+            Action::CreateDir(path) | Action::Unfold(path) => {
+                if let Ok(relative) = path.strip_prefix(&self.target_dir) {
+                    self.created_dirs.insert(relative.to_path_buf());
+                }
+            }
+            Action::RemoveDir(path) => {
+                if let Ok(relative) = path.strip_prefix(&self.target_dir) {
+                    self.created_dirs.remove(relative);
+                }
+            }
+            Action::Copy { source, path } | Action::Render { source, path, .. } => {
+                if let Ok(relative) = path.strip_prefix(&self.target_dir) {
+                    let copy = CopyRecord {
+                        hash: content_hash(path)?,
+                        source: source.clone(),
+                    };
+                    self.copies.insert(relative.to_path_buf(), copy);
+                }
+            }
+            Action::Link(_)
+            | Action::Unlink(_)
+            | Action::Adopt(_)
+            | Action::Rename { .. }
+            | Action::Hardlink { .. }
+            | Action::RemoveFile(_) => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::tests::{create_files, setup};
+
+    #[test]
+    fn test_origin_of() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.profile", "a/.vimrc"]);
+        let source = |name: &str| stow_dir.join("a").join(name);
+
+        let mut state = State::load(&target_dir).unwrap();
+        for name in [".bashrc", ".profile"] {
+            let action = Action::Copy {
+                source: source(name),
+                path: target_dir.join(name),
+            };
+            action.execute().unwrap();
+            state.record(&action).unwrap();
+        }
+        std::fs::hard_link(source(".vimrc"), target_dir.join(".vimrc")).unwrap();
+        write(target_dir.join(".profile"), "changed").unwrap();
+        state.save().unwrap();
+
+        let state = State::load(&target_dir).unwrap();
+        let origin = |name: &str| state.origin_of(&target_dir.join(name), &source(name)).unwrap();
+        assert_eq!(origin(".bashrc"), FileOrigin::Copy);
+        assert_eq!(origin(".profile"), FileOrigin::ModifiedCopy);
+        assert_eq!(origin(".vimrc"), FileOrigin::Hardlink);
+        assert_eq!(
+            state.origin_of(&target_dir.join(".bashrc"), &source(".profile")).unwrap(),
+            FileOrigin::Foreign
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Whether packages are stowed into a target.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::fs::{InstallMode, InstalledItem, Package, Target};
+use crate::log::Log;
+use crate::plan::{Operation, Options, Planner};
+use crate::state::State;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageStatus {
+    /// Every entry of the package is linked into the target.
+    Stowed,
+    /// Some entries are linked, and the rest can be.
+    PartiallyStowed,
+    /// No entries are linked, and all of them can be.
+    NotStowed,
+    /// Stowing the package would conflict with what is in the target.
+    Conflicting,
+    /// The package cannot be read, e.g. because its `syra.toml` is invalid.
+    Unreadable,
+}
+
+impl fmt::Display for PackageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackageStatus::Stowed => write!(f, "stowed"),
+            PackageStatus::PartiallyStowed => write!(f, "partially stowed"),
+            PackageStatus::NotStowed => write!(f, "not stowed"),
+            PackageStatus::Conflicting => write!(f, "conflicting"),
+            PackageStatus::Unreadable => write!(f, "unreadable"),
+        }
+    }
+}
+
+/// Returns the status of `package`, whose entries are installed in `target`
+/// as described by `items`, along with the conflicts that stowing it would
+/// cause.
+pub fn package_status<T: Target, P: Package>(
+    target: &T,
+    state: &State,
+    options: &Options,
+    package: &P,
+    items: &[InstalledItem],
+) -> Result<(PackageStatus, Vec<Error>), Error> {
+    let options = Options {
+        log: Log::default(),
+        install_mode: installed_mode(state, items).unwrap_or(options.install_mode),
+        ..options.clone()
+    };
+    let mut planner = Planner::new(target, state, options);
+    planner.add(Operation::Stow, package)?;
+    let plan = planner.finish();
+
+    let any_owned = items.iter().any(InstalledItem::is_owned);
+    let status = if !plan.conflicts.is_empty() {
+        PackageStatus::Conflicting
+    } else if plan.actions.is_empty() {
+        PackageStatus::Stowed
+    } else if any_owned {
+        PackageStatus::PartiallyStowed
+    } else {
+        PackageStatus::NotStowed
+    };
+
+    Ok((status, plan.conflicts))
+}
+
+/// Returns the mode that the entries described by `items` were installed
+/// with, if any are. A package counts as stowed whichever mode it was stowed
+/// with, as it does whichever link style its links use. Rendered templates
+/// are recorded as copies in every mode, so links win over copies.
+fn installed_mode(state: &State, items: &[InstalledItem]) -> Option<InstallMode> {
+    let mut installed = items.iter().filter_map(|item| match item {
+        InstalledItem::Item(_) => Some(InstallMode::Symlink),
+        InstalledItem::Installed(path) if state.is_copy(path) => Some(InstallMode::Copy),
+        InstalledItem::Installed(_) => Some(InstallMode::Hardlink),
+        _ => None,
+    });
+    let first = installed.next()?;
+    Some(installed.fold(first, |mode, other| match (mode, other) {
+        (InstallMode::Symlink, _) | (_, InstallMode::Symlink) => InstallMode::Symlink,
+        (InstallMode::Hardlink, _) | (_, InstallMode::Hardlink) => InstallMode::Hardlink,
+        _ => InstallMode::Copy,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{PackageImpl, TargetImpl};
+    use crate::plan::tests::{create_files, run, run_with, setup};
+    use std::path::Path;
+
+    fn status(stow_dir: &Path, target_dir: &Path, pkg: &str) -> PackageStatus {
+        let package = PackageImpl::new(stow_dir, pkg).unwrap();
+        let target = TargetImpl::new(target_dir).unwrap();
+        let state = State::load(target_dir).unwrap();
+        let items = target.get_installed_package_contents(&package, &state).unwrap();
+        package_status(&target, &state, &Options::default(), &package, &items).unwrap().0
+    }
+
+    #[test]
+    fn test_package_status() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "b/.vimrc", "c/.profile", "c/.inputrc"]);
+        create_files(&target_dir, &[".vimrc"]);
+
+        run(&stow_dir, &target_dir, &[(Operation::Stow, "a"), (Operation::Stow, "c")]).unwrap();
+        std::fs::remove_file(target_dir.join(".inputrc")).unwrap();
+
+        assert_eq!(status(&stow_dir, &target_dir, "a"), PackageStatus::Stowed);
+        assert_eq!(status(&stow_dir, &target_dir, "b"), PackageStatus::Conflicting);
+        assert_eq!(status(&stow_dir, &target_dir, "c"), PackageStatus::PartiallyStowed);
+
+        run(&stow_dir, &target_dir, &[(Operation::Unstow, "a")]).unwrap();
+        assert_eq!(status(&stow_dir, &target_dir, "a"), PackageStatus::NotStowed);
+    }
+
+    #[test]
+    fn test_package_status_in_any_install_mode() {
+        let (_tmp, stow_dir, target_dir) = setup();
+        create_files(&stow_dir, &["a/.bashrc", "a/.config/app/rc", "b/.vimrc"]);
+        for (mode, package) in [(InstallMode::Copy, "a"), (InstallMode::Hardlink, "b")] {
+            let options = Options {
+                install_mode: mode,
+                ..Default::default()
+            };
+            run_with(options, &stow_dir, &target_dir, &[(Operation::Stow, package)]).unwrap();
+        }
+
+        assert_eq!(status(&stow_dir, &target_dir, "a"), PackageStatus::Stowed);
+        assert_eq!(status(&stow_dir, &target_dir, "b"), PackageStatus::Stowed);
+
+        std::fs::remove_file(target_dir.join(".config/app/rc")).unwrap();
+        assert_eq!(status(&stow_dir, &target_dir, "a"), PackageStatus::PartiallyStowed);
+    }
+}
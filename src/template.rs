@@ -0,0 +1,177 @@
+//! Rendering of package files that are templates.
+//!
+//! A package file is a template if its name ends in `.tmpl`, or if it matches
+//! one of the patterns in the package's `.syra-templates` file, which uses the
+//! syntax of ignore files. Templates are rendered with minijinja and written
+//! to the target as regular files, named without the `.tmpl` suffix. Since a
+//! link to a directory would expose its templates unrendered, directories
+//! containing templates are never folded.
+//!
+//! Templates see these variables:
+//! - `hostname`, the name of the machine
+//! - `os` and `arch`, as in `std::env::consts`, e.g. `linux` and `x86_64`
+//! - `user`, the name of the user running syra
+//! - `class`, the class given with `--class` or in the configuration files
+//! - `env`, the environment variables
+//! - `vars`, the variables given with `--var` and in `[vars]` of the
+//!   configuration files
+//!
+//! Using an undefined variable is an error, so that a typo does not silently
+//! render as an empty string.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+
+use crate::error::{Error, IoAction, IoContext};
+
+pub const SUFFIX: &str = ".tmpl";
+pub const PACKAGE_TEMPLATES_FILE: &str = ".syra-templates";
+
+/// The variables that templates are rendered with.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Variables {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub user: String,
+    pub class: String,
+    pub env: BTreeMap<String, String>,
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Variables {
+    /// Collects the variables of the machine syra runs on. `vars` are the
+    /// user variables as `NAME=VALUE`, later ones overriding earlier ones.
+    pub fn new<S: AsRef<str>>(vars: &[S]) -> Result<Self, Error> {
+        let mut variables = Self {
+            hostname: hostname(),
+            os: env::consts::OS.to_string(),
+            arch: env::consts::ARCH.to_string(),
+            user: user(),
+            class: String::new(),
+            env: utf8_vars(env::vars_os()),
+            vars: BTreeMap::new(),
+        };
+        for var in vars {
+            let var = var.as_ref();
+            let (name, value) = var
+                .split_once('=')
+                .ok_or_else(|| Error::InvalidVariable(var.to_string()))?;
+            variables.vars.insert(name.to_string(), value.to_string());
+        }
+        Ok(variables)
+    }
+
+    /// Sets the class that alternates are selected for.
+    pub fn with_class(mut self, class: Option<&str>) -> Self {
+        self.class = class.unwrap_or_default().to_string();
+        self
+    }
+}
+
+/// Returns the environment variables in `vars` whose name and value are
+/// valid UTF-8. Templates cannot use the others.
+fn utf8_vars(vars: impl Iterator<Item = (OsString, OsString)>) -> BTreeMap<String, String> {
+    vars.filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .collect()
+}
+
+/// Returns the name of the machine, without its domain.
+pub fn hostname() -> String {
+    let name = gethostname::gethostname().to_string_lossy().into_owned();
+    match name.split_once('.') {
+        Some((host, _)) => host.to_string(),
+        None => name,
+    }
+}
+
+/// Returns the name of the user running syra.
+pub fn user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
+/// Returns the path that the template `item` is installed as: the same path
+/// without the `.tmpl` suffix, if it has one.
+pub fn rendered_path(item: &Path) -> PathBuf {
+    let name = item.file_name().unwrap_or_default().to_string_lossy();
+    match name.strip_suffix(SUFFIX) {
+        Some(stem) if !stem.is_empty() => item.with_file_name(OsString::from(stem)),
+        _ => item.to_path_buf(),
+    }
+}
+
+/// Returns true if `item`, a path relative to the package root, is named like
+/// a template.
+pub fn has_template_suffix(item: &Path) -> bool {
+    rendered_path(item) != item
+}
+
+/// Renders the template at `path` with `variables`.
+pub fn render(path: &Path, variables: &Variables) -> Result<String, Error> {
+    let source = std::fs::read_to_string(path).at(IoAction::Read, path)?;
+
+    let mut environment = Environment::new();
+    environment.set_undefined_behavior(UndefinedBehavior::Strict);
+    environment.set_keep_trailing_newline(true);
+    let name = path.to_string_lossy();
+    let template_error = |e: minijinja::Error| Error::Template(path.to_path_buf(), e.to_string());
+    environment.add_template(&name, &source).map_err(template_error)?;
+    environment
+        .get_template(&name)
+        .and_then(|template| template.render(variables))
+        .map_err(template_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStringExt;
+
+    #[test]
+    fn test_utf8_vars_skip_other_vars() {
+        let vars = vec![
+            (OsString::from("HOME"), OsString::from("/home/me")),
+            (OsString::from("BAD"), OsString::from_vec(vec![0xff])),
+            (OsString::from_vec(vec![b'X', 0xff]), OsString::from("value")),
+        ];
+
+        let vars = utf8_vars(vars.into_iter());
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars["HOME"], "/home/me");
+    }
+
+    #[test]
+    fn test_rendered_path() {
+        assert_eq!(rendered_path(Path::new(".ssh/config.tmpl")), PathBuf::from(".ssh/config"));
+        assert_eq!(rendered_path(Path::new(".gitconfig")), PathBuf::from(".gitconfig"));
+        assert_eq!(rendered_path(Path::new(".tmpl")), PathBuf::from(".tmpl"));
+    }
+
+    #[test]
+    fn test_render() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("gitconfig.tmpl");
+        std::fs::write(
+            &path,
+            "[user]\n{% if hostname == 'work' %}\temail = {{ vars.work_email }}\n{% else %}\temail = me@home\n{% endif %}",
+        )
+        .unwrap();
+        let mut variables = Variables::new(&["work_email=me@work"]).unwrap();
+
+        variables.hostname = "work".to_string();
+        assert_eq!(render(&path, &variables).unwrap(), "[user]\n\temail = me@work\n");
+        variables.hostname = "laptop".to_string();
+        assert_eq!(render(&path, &variables).unwrap(), "[user]\n\temail = me@home\n");
+
+        std::fs::write(&path, "{{ vars.missing }}").unwrap();
+        assert!(matches!(render(&path, &variables), Err(Error::Template(..))));
+        assert!(matches!(Variables::new(&["novalue"]), Err(Error::InvalidVariable(_))));
+    }
+}
@@ -0,0 +1,25 @@
+#![cfg(feature = "cli")]
+
+use std::fs::{create_dir_all, write};
+use std::process::Command;
+
+#[test]
+fn test_json_output_is_a_single_document_with_verbose() {
+    let tmp = tempfile::tempdir().unwrap();
+    create_dir_all(tmp.path().join("stow/a")).unwrap();
+    create_dir_all(tmp.path().join("target")).unwrap();
+    write(tmp.path().join("stow/a/.bashrc"), "").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_syra"))
+        .args(["--output", "json", "stow", "-v", "-d", "stow", "-t", "target", "a"])
+        .current_dir(tmp.path())
+        .env("HOME", tmp.path())
+        .env("XDG_CONFIG_HOME", tmp.path().join(".config"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["command"], "stow");
+    assert!(tmp.path().join("target/.bashrc").is_symlink());
+}